@@ -1,58 +1,449 @@
-use std::{error::Error, io};
+use std::io::{self, Read};
 
 use assembler::assemble;
 use assembly_generator::generate_assembly;
 use base64::{engine::general_purpose, Engine};
+use cst::build_cst;
+use diagnostic::{Diagnostic, Severity};
+use error_codes::ErrorCode;
 use interpreter::interpret;
 use ir_generator::generate_ir;
-use parser::parse;
+use lints::check_lints;
+pub use lints::LintConfig;
+use macro_expander::{expand_macros, FreshNames};
+use parser::{parse, parse_with_max_depth, DEFAULT_MAX_NESTING_DEPTH};
 use symtab::SymTab;
-use tokenizer::tokenize;
+use tokenizer::{tokenize, tokenize_lossless};
 use type_checker::type_check;
+use variable::set_program_args;
+use warnings::{check_warnings, WarningOptions};
 
 mod assembler;
 mod assembly_generator;
 mod ast;
+mod cst;
+mod diagnostic;
+mod error_codes;
 mod interpreter;
 mod ir;
 mod ir_generator;
+mod lints;
+mod macro_expander;
 mod parser;
+mod suggestions;
 mod symtab;
 mod token;
 mod tokenizer;
 mod type_checker;
 mod variable;
+mod warnings;
 
-pub fn compile(code: &str) -> Result<String, Box<dyn Error>> {
-    let tokens = tokenize(code)?;
-    let mut ast = parse(&tokens)?;
+// The parser and the passes over the AST are recursive, with their depth bounded by the nesting
+// limit. Threads running the compiler or the interpreter need a stack this large to reach it.
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct CompilerOptions {
+    // Exit with the final Int value of the program instead of printing it
+    pub exit_with_result: bool,
+    pub max_nesting_depth: usize,
+    pub warnings: WarningOptions,
+    pub lints: LintConfig,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        Self {
+            exit_with_result: false,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            warnings: WarningOptions::default(),
+            lints: LintConfig::default(),
+        }
+    }
+}
+
+// Returns the program along with any warnings. With warnings treated as errors, or any lint set to
+// deny failing, the warnings are returned as the errors instead.
+pub fn compile(
+    code: &str,
+    options: &CompilerOptions,
+) -> Result<(String, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let names = FreshNames::default();
+    let tokens = tokenize(code).map_err(|error| vec![error.into()])?;
+    let mut ast = parse_with_max_depth(&tokens, options.max_nesting_depth)?;
+    expand_macros(&mut ast, &names).map_err(|error| vec![error.into()])?;
     type_check(&mut ast, &mut SymTab::new_type_table())?;
-    let ir = generate_ir(&ast);
-    let assembly = generate_assembly(&ir);
 
-    Ok(general_purpose::STANDARD.encode(assemble(assembly)))
+    let mut warnings = check_warnings(&ast, options.warnings);
+    warnings.extend(check_lints(&ast, code, &options.lints));
+    warnings.sort_by_key(|warning| warning.primary.span.start);
+    // -Werror applies to lints as well as warnings
+    if options.warnings.as_errors {
+        for warning in warnings.iter_mut() {
+            warning.severity = Severity::Error;
+        }
+    }
+    if warnings
+        .iter()
+        .any(|warning| warning.severity == Severity::Error)
+    {
+        return Err(warnings);
+    }
+
+    let ir = generate_ir(&ast, options.exit_with_result).map_err(|error| vec![error.into()])?;
+    let assembly = generate_assembly(&ir).map_err(|error| vec![error.into()])?;
+    let program = assemble(assembly).map_err(|error| vec![error.into()])?;
+
+    Ok((general_purpose::STANDARD.encode(program), warnings))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MessageFormat {
+    // Rendered with the source lines they point at
+    #[default]
+    Human,
+    // One JSON object per diagnostic, each on its own line
+    Json,
+}
+
+impl MessageFormat {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!(
+                "Unknown message format {}, expected human or json",
+                name
+            )),
+        }
+    }
 }
 
-pub fn start_compiler() {
+// Programs read from standard input are reported as this file
+const STDIN_FILE: &str = "<stdin>";
+
+pub fn start_compiler(options: CompilerOptions, format: MessageFormat) {
     let lines = io::stdin().lines();
     for line in lines.map_while(Result::ok) {
-        match compile(&line) {
-            Ok(_) => println!("\nCompilation OK :)\n"),
-            Err(e) => println!("\n{e}\n"),
+        let result = compile(&line, &options);
+
+        if format == MessageFormat::Json {
+            let (Ok((_, diagnostics)) | Err(diagnostics)) = result;
+            for diagnostic in diagnostics {
+                println!("{}", diagnostic.to_json(&line, STDIN_FILE).dump());
+            }
+            continue;
+        }
+
+        match result {
+            Ok((_, warnings)) => println!(
+                "\n{}Compilation OK :)\n",
+                render_diagnostics(&warnings, &line)
+            ),
+            Err(diagnostics) => println!("\n{}", render_diagnostics(&diagnostics, &line)),
         }
     }
 }
 
-pub fn start_interpreter() {
+pub fn diagnostics_json(diagnostics: &[Diagnostic], code: &str, file: &str) -> json::JsonValue {
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.to_json(code, file))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+// Every diagnostic with the lines of source it points at, separated by blank lines
+pub fn render_diagnostics(diagnostics: &[Diagnostic], code: &str) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render(code) + "\n")
+        .collect()
+}
+
+// Prints the long-form explanation of an error code, or lists every code if none is given
+pub fn start_explain(code: Option<&str>) {
+    match code.map(|code| (code, ErrorCode::from_code(code))) {
+        Some((_, Some(error_code))) => print!("{}", error_code.explain()),
+        Some((code, None)) => eprintln!("Unknown error code {code}"),
+        None => {
+            for error_code in ErrorCode::ALL {
+                println!("{}: {}", error_code, error_code.title());
+            }
+        }
+    }
+}
+
+// Reprints the whole program from its concrete syntax tree. There are no formatting rules yet,
+// so the output is identical to the input. With outline set, the tree itself is printed instead.
+pub fn start_formatter(outline: bool) {
+    let mut code = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut code) {
+        println!("{e}");
+        return;
+    }
+
+    match format_code(&code, outline) {
+        Ok(formatted) => print!("{formatted}"),
+        Err(diagnostics) => println!("\n{}", render_diagnostics(&diagnostics, &code)),
+    }
+}
+
+fn format_code(code: &str, outline: bool) -> Result<String, Vec<Diagnostic>> {
+    let (tokens, trailing_trivia) = tokenize_lossless(code).map_err(|error| vec![error.into()])?;
+    let ast = parse(&tokens)?;
+    let cst = build_cst(&ast, &tokens, trailing_trivia);
+
+    Ok(if outline {
+        cst.outline()
+    } else {
+        cst.to_string()
+    })
+}
+
+pub fn start_interpreter(program_args: Vec<String>) {
+    set_program_args(program_args);
+
     let lines = io::stdin().lines();
     #[allow(clippy::manual_flatten)]
     for line in lines {
         if let Ok(code) = line {
-            let tokens = tokenize(&code).unwrap();
-            let ast = parse(&tokens).unwrap();
+            match interpret_line(&code) {
+                Ok(output) => println!("{}", output),
+                Err(diagnostics) => print!("{}", render_diagnostics(&diagnostics, &code)),
+            }
+        }
+    }
+}
+
+// Each line is a program of its own, so an error or a call to exit only ends the line it's on
+fn interpret_line(code: &str) -> Result<String, Vec<Diagnostic>> {
+    let names = FreshNames::default();
+    let tokens = tokenize(code).map_err(|error| vec![error.into()])?;
+    let mut ast = parse(&tokens)?;
+    expand_macros(&mut ast, &names).map_err(|error| vec![error.into()])?;
+
+    match interpret(&ast, &mut SymTab::new_val_table()) {
+        Ok(val) => Ok(val.to_string()),
+        Err(error) => match error.exit_code {
+            Some(code) => Ok(format!("Exited with code {}", code)),
+            None => Err(vec![error.into()]),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: [&str; 6] = [
+        "var x = 1; while x < 10 do { x = x + 1 }; x",
+        "var a: Int = read_int(); if a > 0 and true then print_int(a) else print(-a)",
+        "{ defer print_int(1); var b = not false; b }",
+        "macro twice(e) { e; e }; twice(print_int(2))",
+        "infixl 7 <+> = max; var max = 0; 1 <+> 2",
+        "var f = print_int; assert(1 == 1); exit(2)",
+    ];
+
+    const FRAGMENTS: [&str; 40] = [
+        "var",
+        "x",
+        "y",
+        "=",
+        ":",
+        "Int",
+        "Bool",
+        "Unit",
+        "if",
+        "then",
+        "else",
+        "while",
+        "do",
+        "{",
+        "}",
+        "(",
+        ")",
+        ";",
+        ",",
+        "1",
+        "9223372036854775808",
+        "true",
+        "false",
+        "+",
+        "-",
+        "*",
+        "/",
+        "%",
+        "==",
+        "<",
+        "and",
+        "or",
+        "not",
+        "print_int",
+        "read_int",
+        "exit",
+        "defer",
+        "macro",
+        "infixl",
+        "<+>",
+    ];
+
+    // Deterministic xorshift, so failures are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn assert_no_panic(programs: Vec<String>) {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                for code in programs {
+                    let result = std::panic::catch_unwind(|| {
+                        let _ = compile(&code, &CompilerOptions::default());
+                    });
+                    assert!(result.is_ok(), "compile panicked on {code:?}");
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_compile_errors_located() {
+        let errors = compile("var f = print_int; 1", &CompilerOptions::default()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].msg,
+            "print_int is a function and can only be called"
+        );
+        assert_eq!(errors[0].code, Some(ErrorCode::FunctionValue));
+        assert_eq!(errors[0].primary.span, token::Span::new(8, 17));
+    }
+
+    fn run_compiled(code: &str) -> Option<i32> {
+        use std::{fs, os::unix::fs::PermissionsExt, process};
+
+        let (program, _) = compile(code, &CompilerOptions::default()).unwrap();
+        let dir = tempfile::TempDir::with_prefix("compiler_test_").unwrap();
+        let path = dir.path().join("a.out");
+        fs::write(&path, general_purpose::STANDARD.decode(program).unwrap()).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        process::Command::new(&path).status().unwrap().code()
+    }
+
+    #[test]
+    fn test_exit_code_matches_compiled() {
+        for code in [3, 256, -1] {
+            let program = format!("exit({})", code);
+            let expected = run_compiled(&program).unwrap();
+            assert_eq!(
+                interpret_line(&program).unwrap(),
+                format!("Exited with code {}", expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_warnings_as_errors() {
+        let mut options = CompilerOptions::default();
+        options.warnings.apply_flag("-Werror").unwrap();
+        let errors = compile("var a = 1; while true do 1", &options).unwrap_err();
+        let errors = errors
+            .iter()
+            .map(|error| (error.severity, error.msg.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (Severity::Error, "Unused variable a"),
+                (
+                    Severity::Error,
+                    "Loop never ends, there is no call to exit in its body"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_denied_lints_fail_compilation() {
+        let options = CompilerOptions {
+            lints: LintConfig::parse("infinite-loop = deny").unwrap(),
+            ..Default::default()
+        };
+        let errors = compile("var a = 1; while true do a = 2; a", &options).unwrap_err();
+        let errors = errors
+            .iter()
+            .map(|error| (error.severity, error.msg.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    Severity::Error,
+                    "Loop never ends, there is no call to exit in its body"
+                ),
+                (Severity::Warning, "Unreachable code"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interpret_line_errors() {
+        let errors = interpret_line("1 + $").unwrap_err();
+        assert_eq!(errors[0].msg, "Invalid token starting with '$'");
+
+        let errors = interpret_line("{ var a = 1 }; a").unwrap_err();
+        assert_eq!(errors[0].msg, "No symbol a found!");
+        assert_eq!(errors[0].primary.span, token::Span::new(15, 16));
+
+        assert_eq!(interpret_line("1 + 2").unwrap(), "3");
+        assert_eq!(interpret_line("exit(2); 3").unwrap(), "Exited with code 2");
+    }
+
+    #[test]
+    fn test_random_tokens_never_panic() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        let programs = (0..500)
+            .map(|_| {
+                (0..1 + rng.below(16))
+                    .map(|_| FRAGMENTS[rng.below(FRAGMENTS.len())])
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        assert_no_panic(programs);
+    }
 
-            let val = interpret(&ast, &mut SymTab::new_val_table());
-            println!("{}", val);
+    #[test]
+    fn test_mutated_programs_never_panic() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        let mut programs = Vec::new();
+        for program in PROGRAMS {
+            let words = program.split(' ').collect::<Vec<_>>();
+            for _ in 0..40 {
+                // Drop, duplicate or replace a single word, or cut the program short
+                let mut mutated = words.clone();
+                let i = rng.below(words.len());
+                match rng.below(4) {
+                    0 => {
+                        mutated.remove(i);
+                    }
+                    1 => mutated.insert(i, words[i]),
+                    2 => mutated[i] = FRAGMENTS[rng.below(FRAGMENTS.len())],
+                    _ => mutated.truncate(i),
+                }
+                programs.push(mutated.join(" "));
+            }
         }
+        assert_no_panic(programs);
     }
 }
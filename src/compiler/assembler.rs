@@ -1,66 +1,99 @@
-use std::fs::File;
-use std::io::{Read, Write};
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
 use std::process::Command;
 use tempfile::TempDir;
 
-pub fn assemble(assembly: String) -> Vec<u8> {
-    let tmp_dir = TempDir::with_prefix("compiler_").expect("Failed to create temp directory");
+use crate::compiler::{
+    diagnostic::Diagnostic,
+    token::{CodeLocation, Span},
+};
+
+// Failures of the environment rather than of the program, like a missing 'as' command
+#[derive(Debug)]
+pub struct AssemblerError {
+    msg: String,
+}
+
+impl Display for AssemblerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AssemblerError: {}", self.msg)
+    }
+}
+
+impl Error for AssemblerError {}
+
+impl AssemblerError {
+    fn new(msg: String) -> Self {
+        Self { msg }
+    }
+}
+
+// There's no code to point at, so the error is reported at the start of the program
+impl From<AssemblerError> for Diagnostic {
+    fn from(error: AssemblerError) -> Self {
+        Diagnostic::error(error.msg, CodeLocation::default(), Span::default())
+    }
+}
+
+pub fn assemble(assembly: String) -> Result<Vec<u8>, AssemblerError> {
+    let tmp_dir = TempDir::with_prefix("compiler_")
+        .map_err(|e| AssemblerError::new(format!("Failed to create temp directory: {e}")))?;
     let workdir = tmp_dir.path();
 
-    let stdlib_asm = &workdir
-        .join("stdlib.s")
-        .into_os_string()
-        .into_string()
-        .unwrap();
-    let stdlib_obj = &workdir
-        .join("stdlib.o")
-        .into_os_string()
-        .into_string()
-        .unwrap();
-    let program_asm = &workdir
-        .join("program.s")
-        .into_os_string()
-        .into_string()
-        .unwrap();
-    let program_obj = &workdir
-        .join("program.o")
-        .into_os_string()
-        .into_string()
-        .unwrap();
-    let output_file = &workdir
-        .join("a.out")
-        .into_os_string()
-        .into_string()
-        .unwrap();
-
-    let mut file = File::create(stdlib_asm.clone()).expect("Failed to write temp file!");
-    file.write_all(STDLIB_ASM_CODE.as_bytes())
-        .expect("Can't write to temp file!");
-
-    let mut file = File::create(program_asm.clone()).expect("Failed to write temp file!");
-    file.write_all(assembly.as_bytes())
-        .expect("Can't write to temp file!");
-
-    Command::new("as")
-        .args(["-g", "-o", stdlib_obj, stdlib_asm])
-        .output()
-        .expect("Could not run 'as' command!");
+    let stdlib_asm = &workdir.join("stdlib.s");
+    let stdlib_obj = &workdir.join("stdlib.o");
+    let program_asm = &workdir.join("program.s");
+    let program_obj = &workdir.join("program.o");
+    let output_file = &workdir.join("a.out");
+
+    write_file(stdlib_asm, STDLIB_ASM_CODE)?;
+    write_file(program_asm, &assembly)?;
+
+    run(
+        "as",
+        &["-g".as_ref(), "-o".as_ref(), stdlib_obj, stdlib_asm],
+    )?;
+    run(
+        "as",
+        &["-g".as_ref(), "-o".as_ref(), program_obj, program_asm],
+    )?;
+    run(
+        "ld",
+        &[
+            "-o".as_ref(),
+            output_file,
+            "-static".as_ref(),
+            stdlib_obj,
+            program_obj,
+        ],
+    )?;
+
+    fs::read(output_file)
+        .map_err(|e| AssemblerError::new(format!("Can't read compiler output: {e}")))
+}
 
-    Command::new("as")
-        .args(["-g", "-o", program_obj, program_asm])
-        .output()
-        .expect("Could not run 'as' command!");
+fn write_file(path: &Path, contents: &str) -> Result<(), AssemblerError> {
+    fs::write(path, contents)
+        .map_err(|e| AssemblerError::new(format!("Can't write to temp file: {e}")))
+}
 
-    Command::new("ld")
-        .args(["-o", output_file, "-static", stdlib_obj, program_obj])
+// Runs the command, failing unless it exits successfully
+fn run(command: &str, args: &[&Path]) -> Result<(), AssemblerError> {
+    let output = Command::new(command)
+        .args(args)
         .output()
-        .expect("Could not run 'as' command!");
-
-    let mut file = File::open(output_file).expect("Can't open compiler output!");
-    let mut output = Vec::new();
-    file.read_to_end(&mut output)
-        .expect("Can't read compiler output!");
-    output
+        .map_err(|e| AssemblerError::new(format!("Could not run '{command}' command: {e}")))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(AssemblerError::new(format!(
+            "'{command}' failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )))
+    }
 }
 
 const STDLIB_ASM_CODE: &str = "
@@ -68,17 +101,26 @@ const STDLIB_ASM_CODE: &str = "
     .global print_int
     .global print_bool
     .global read_int
+    .global assert_fail
+    .global exit
+    .global arg_count
+    .global arg_int
     .extern main
     .section .text
 
 # BEGIN START (we skip this part when linking with C)
 # ***** Function '_start' *****
-# Calls function 'main' and halts the program
+# Saves the command-line arguments, calls function 'main' and halts the program
+# with the return value of 'main' as the exit code
 
 _start:
+    movq (%rsp), %rax        # The stack starts with 'argc'
+    movq %rax, argc_value
+    leaq 8(%rsp), %rax       # followed by the 'argv' array
+    movq %rax, argv_value
     call main
+    movq %rax, %rdi          # Exit with the return value of 'main'
     movq $60, %rax
-    xorq %rdi, %rdi
     syscall
 # END START
 
@@ -318,4 +360,183 @@ read_int:
 read_int_error_str:
     .ascii \"Error: read_int() failed to read input\\n\"
 read_int_error_str_len = . - read_int_error_str
+
+# ***** Function 'assert_fail' *****
+# Prints 'assertion failed at LINE:COLUMN' to stderr and exits the program
+# with exit code 1. Never returns.
+#
+# The message is built backwards in a buffer on the stack, like in 'print_int'.
+#
+# Registers:
+# - rdi = the line number of the failed assertion
+# - rsi = the column of the failed assertion
+# - r9 = pointer to the first byte of the message built so far
+# - rax, rcx, rdx and r10 are used by intermediate computations
+assert_fail:
+    pushq %rbp               # Save previous stack frame pointer
+    movq %rsp, %rbp          # Set stack frame pointer
+    subq $64, %rsp           # Reserve space for the message
+    movq %rbp, %r9           # The message ends where the buffer ends
+
+    decq %r9
+    movb $10, (%r9)          # ASCII newline = 10
+    movq %rsi, %rax
+    call .Lassert_fail_digits
+    decq %r9
+    movb $58, (%r9)          # ASCII ':' = 58
+    movq %rdi, %rax
+    call .Lassert_fail_digits
+
+    # Copy the constant prefix in front of the location, last byte first
+    movq $assert_fail_str_len, %rcx
+.Lassert_fail_prefix:
+    decq %r9
+    movb assert_fail_str-1(%rcx), %dl
+    movb %dl, (%r9)
+    decq %rcx
+    jnz .Lassert_fail_prefix
+
+    # Write the message to stderr with syscall 'write'
+    movq $1, %rax
+    movq $2, %rdi
+    movq %r9, %rsi
+    movq %rbp, %rdx
+    subq %r9, %rdx
+    syscall
+
+    # Exit the program
+    movq $60, %rax      # Syscall number for exit = 60.
+    movq $1, %rdi       # Set exit code 1.
+    syscall
+
+# Writes the digits of the non-negative number in rax in front of r9
+.Lassert_fail_digits:
+    movq $10, %r10
+.Lassert_fail_digit_loop:
+    cqto
+    idivq %r10               # Sets rax = quotient and rdx = remainder
+    addq $48, %rdx           # ASCII '0' = 48
+    decq %r9
+    movb %dl, (%r9)
+    cmpq $0, %rax
+    jne .Lassert_fail_digit_loop
+    ret
+
+assert_fail_str:
+    .ascii \"assertion failed at \"
+assert_fail_str_len = . - assert_fail_str
+
+# ***** Function 'exit' *****
+# Exits the program with the given exit code. Never returns.
+exit:
+    movq $60, %rax      # Syscall number for exit = 60. Exit code is already in rdi.
+    syscall
+
+# ***** Function 'arg_count' *****
+# Returns the number of command-line arguments, not counting the program name.
+arg_count:
+    movq argc_value, %rax
+    decq %rax
+    ret
+
+# ***** Function 'arg_int' *****
+# Parses the command-line argument with the given index as a 64-bit signed integer.
+# Index 0 is the first argument after the program name.
+#
+# It crashes the program if there is no such argument, if the argument
+# is not an optional minus sign followed by digits, or if it doesn't fit
+# in 64 bits.
+#
+# Registers:
+# - rdi = the index of the argument
+# - rsi = pointer to the next character of the argument
+# - r9 = whether the number is negative
+# - r10 = accumulates our output
+# - r8 = the current character
+arg_int:
+    pushq %rbp               # Save previous stack frame pointer
+    movq %rsp, %rbp          # Set stack frame pointer
+
+    # Check that the argument exists
+    cmpq $0, %rdi
+    jl .Larg_int_error
+    movq argc_value, %rax
+    decq %rax
+    cmpq %rax, %rdi
+    jge .Larg_int_error
+
+    movq argv_value, %rsi
+    movq 8(%rsi,%rdi,8), %rsi    # Skip the program name
+    xorq %r9, %r9
+    xorq %r10, %r10
+
+    # If the first byte is 45 (minus sign), set r9
+    movzbq (%rsi), %r8
+    cmpq $45, %r8
+    jne .Larg_int_sign_done
+    incq %r9
+    incq %rsi
+.Larg_int_sign_done:
+
+    # There must be at least one digit
+    movzbq (%rsi), %r8
+    cmpq $0, %r8
+    je .Larg_int_error
+
+.Larg_int_loop:
+    movzbq (%rsi), %r8
+    cmpq $0, %r8
+    je .Larg_int_end         # Loop done at the terminating zero byte
+
+    # The byte must be between 48 ('0') and 57 ('9')
+    cmpq $48, %r8
+    jl .Larg_int_error
+    cmpq $57, %r8
+    jg .Larg_int_error
+
+    # Shift the digit onto the result. It's built up as a negative number,
+    # which has room for the smallest integer, and overflowing is an error.
+    subq $48, %r8
+    imulq $10, %r10
+    jo .Larg_int_error
+    subq %r8, %r10
+    jo .Larg_int_error
+    incq %rsi
+    jmp .Larg_int_loop
+
+.Larg_int_end:
+    # If it's not a negative number, negate the result
+    cmpq $0, %r9
+    jne .Larg_int_negation_done
+    negq %r10
+    jo .Larg_int_error
+.Larg_int_negation_done:
+    # Restore stack registers and return the result
+    movq %rbp, %rsp
+    popq %rbp
+    movq %r10, %rax
+    ret
+
+.Larg_int_error:
+    # Write error message to stderr with syscall 'write'
+    movq $1, %rax
+    movq $2, %rdi
+    movq $arg_int_error_str, %rsi
+    movq $arg_int_error_str_len, %rdx
+    syscall
+
+    # Exit the program
+    movq $60, %rax      # Syscall number for exit = 60.
+    movq $1, %rdi       # Set exit code 1.
+    syscall
+
+arg_int_error_str:
+    .ascii \"Error: arg_int() failed to read an integer argument\\n\"
+arg_int_error_str_len = . - arg_int_error_str
+
+    .section .data
+argc_value:
+    .quad 0
+argv_value:
+    .quad 0
 ";
@@ -1,30 +1,69 @@
-use std::collections::{HashMap, HashSet};
-
-use crate::compiler::ir::{IrInstruction, IrVar};
-
-pub fn generate_assembly(instructions: &Vec<IrInstruction>) -> String {
-    const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-    const INSTRINSICS: [&str; 13] = [
-        "+",
-        "*",
-        "-",
-        "/",
-        "%",
-        "<",
-        "<=",
-        ">",
-        ">=",
-        "==",
-        "!=",
-        "unary_not",
-        "unary_-",
-    ];
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+};
+
+use crate::compiler::{
+    diagnostic::Diagnostic,
+    ir::{IrInstruction, IrVar},
+    token::{CodeLocation, Span},
+};
+
+const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+const INSTRINSICS: [&str; 15] = [
+    "+",
+    "*",
+    "-",
+    "/",
+    "%",
+    "<",
+    "<=",
+    ">",
+    ">=",
+    "==_int",
+    "==_bool",
+    "!=_int",
+    "!=_bool",
+    "unary_not",
+    "unary_-",
+];
+
+#[derive(Debug)]
+pub struct AssemblyGeneratorError {
+    msg: String,
+    loc: CodeLocation,
+    span: Span,
+}
+
+impl Display for AssemblyGeneratorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AssemblyGeneratorError: {}", self.msg)
+    }
+}
+
+impl Error for AssemblyGeneratorError {}
+
+impl From<AssemblyGeneratorError> for Diagnostic {
+    fn from(error: AssemblyGeneratorError) -> Self {
+        Diagnostic::error(error.msg, error.loc, error.span)
+    }
+}
+
+pub fn generate_assembly(
+    instructions: &Vec<IrInstruction>,
+) -> Result<String, AssemblyGeneratorError> {
     let locals = Locals::new(instructions);
+    let mut assert_count = 0;
 
     let mut out = String::from(
         "\t.extern print_int
 \t.extern print_bool
 \t.extern read_int 
+\t.extern assert_fail
+\t.extern exit
+\t.extern arg_count
+\t.extern arg_int
 \t.global main
 \t.type main, @function
 
@@ -39,88 +78,130 @@ main:
     out.push_str(&format!("\tsubq ${}, %rsp\n", locals.stack_used()));
 
     for instruction in instructions {
-        out.push_str(&format!("\n\t# {}\n", instruction.instruction));
-
-        use crate::compiler::ir::IrInstructionType::*;
-        match &instruction.instruction {
-            LoadBoolConst(val, dest) => {
-                let val = if *val { 1 } else { 0 };
-                out.push_str(&format!("\tmovq ${val}, {}\n", locals.get_ref(dest)));
-            }
-            LoadIntConst(val, dest) => {
-                // x86-64 weirdness with large integers
-                if -2_i64.pow(31) <= *val && *val < 2_i64.pow(31) {
-                    out.push_str(&format!("\tmovq ${val}, {}\n", locals.get_ref(dest)));
-                } else {
-                    out.push_str(&format!("\tmovq ${val}, %rax\n"));
-                    out.push_str(&format!("\tmovq %rax, {}\n", locals.get_ref(dest)));
-                }
+        // Errors are located at the instruction they were found in
+        generate_instruction(&mut out, &locals, instruction, &mut assert_count).map_err(|msg| {
+            AssemblyGeneratorError {
+                msg,
+                loc: instruction.loc,
+                span: instruction.span,
             }
-            Copy(src, dest) => {
-                out.push_str(&format!("\tmovq {}, %rax\n", locals.get_ref(src)));
-                out.push_str(&format!("\tmovq %rax, {}\n", locals.get_ref(dest)));
-            }
-            Call(op_var, arg_vec, output_var) => {
-                assert!(
-                    arg_vec.len() <= 6,
-                    "More than 6 args to a function '{op_var}' !"
-                );
-
-                if INSTRINSICS.contains(&&*op_var.name) {
-                    handle_intrinsics(&mut out, &locals, op_var, arg_vec, output_var);
-                } else {
-                    // Align stack according to spec
-                    if locals.stack_used % 16 != 0 {
-                        out.push_str("\tsubq $8, %rsp\n");
-                    }
+        })?;
+    }
 
-                    for (i, var) in arg_vec.iter().enumerate() {
-                        out.push_str(&format!(
-                            "\tmovq {}, {}\n",
-                            locals.get_ref(var),
-                            ARG_REGISTERS[i]
-                        ));
-                    }
-                    out.push_str(&format!("\tcallq {op_var}\n"));
-                    out.push_str(&format!("\tmovq %rax, {}\n", locals.get_ref(output_var)));
+    out.push_str(
+        "
+\tmovq $0, %rax
+\tmovq %rbp, %rsp
+\tpopq %rbp
+\tret",
+    );
 
-                    // Align stack according to spec
-                    if locals.stack_used % 16 != 0 {
-                        out.push_str("\taddq $8, %rsp\n");
-                    }
-                }
+    Ok(out)
+}
+
+fn generate_instruction(
+    out: &mut String,
+    locals: &Locals,
+    instruction: &IrInstruction,
+    assert_count: &mut usize,
+) -> Result<(), String> {
+    out.push_str(&format!("\n\t# {}\n", instruction.instruction));
+
+    use crate::compiler::ir::IrInstructionType::*;
+    match &instruction.instruction {
+        LoadBoolConst(val, dest) => {
+            let val = if *val { 1 } else { 0 };
+            out.push_str(&format!("\tmovq ${val}, {}\n", locals.get_ref(dest)?));
+        }
+        LoadIntConst(val, dest) => {
+            // x86-64 weirdness with large integers
+            if -2_i64.pow(31) <= *val && *val < 2_i64.pow(31) {
+                out.push_str(&format!("\tmovq ${val}, {}\n", locals.get_ref(dest)?));
+            } else {
+                out.push_str(&format!("\tmovq ${val}, %rax\n"));
+                out.push_str(&format!("\tmovq %rax, {}\n", locals.get_ref(dest)?));
             }
-            Jump(target) => {
-                let Label(target_name) = &target.instruction else {
-                    panic!("Tried to jump to non-label {target}")
-                };
-                out.push_str(&format!("\tjmp .L{target_name}\n"));
+        }
+        Copy(src, dest) => {
+            out.push_str(&format!("\tmovq {}, %rax\n", locals.get_ref(src)?));
+            out.push_str(&format!("\tmovq %rax, {}\n", locals.get_ref(dest)?));
+        }
+        Call(op_var, arg_vec, output_var) => {
+            if arg_vec.len() > ARG_REGISTERS.len() {
+                return Err(format!("More than 6 args to a function '{op_var}'!"));
             }
-            CondJump(cond, jmp_then, jmp_else) => {
-                let Label(then_target) = &jmp_then.instruction else {
-                    panic!("Tried to jump to non-label {jmp_then}")
-                };
-                let Label(else_target) = &jmp_else.instruction else {
-                    panic!("Tried to jump to non-label {jmp_else}")
+
+            if INSTRINSICS.contains(&&*op_var.name) {
+                handle_intrinsics(out, locals, op_var, arg_vec, output_var)?;
+            } else if op_var.name == "assert" {
+                let [cond] = &arg_vec[..] else {
+                    return Err(String::from("Assert takes exactly one argument!"));
                 };
+                *assert_count += 1;
+                handle_assert(out, locals, instruction, cond, *assert_count)?;
+            } else {
+                // Align stack according to spec
+                if locals.stack_used % 16 != 0 {
+                    out.push_str("\tsubq $8, %rsp\n");
+                }
+
+                for (var, register) in arg_vec.iter().zip(ARG_REGISTERS) {
+                    out.push_str(&format!("\tmovq {}, {}\n", locals.get_ref(var)?, register));
+                }
+                out.push_str(&format!("\tcallq {op_var}\n"));
+                out.push_str(&format!("\tmovq %rax, {}\n", locals.get_ref(output_var)?));
 
-                out.push_str(&format!("\tcmpq $0, {}\n", locals.get_ref(cond)));
-                out.push_str(&format!("\tjne .L{then_target}\n"));
-                out.push_str(&format!("\tjmp .L{else_target}\n"));
+                // Align stack according to spec
+                if locals.stack_used % 16 != 0 {
+                    out.push_str("\taddq $8, %rsp\n");
+                }
             }
-            Label(name) => out.push_str(&format!(".L{name}:\n")),
         }
+        Jump(target) => {
+            let Label(target_name) = &target.instruction else {
+                return Err(format!("Tried to jump to non-label {target}"));
+            };
+            out.push_str(&format!("\tjmp .L{target_name}\n"));
+        }
+        CondJump(cond, jmp_then, jmp_else) => {
+            let Label(then_target) = &jmp_then.instruction else {
+                return Err(format!("Tried to jump to non-label {jmp_then}"));
+            };
+            let Label(else_target) = &jmp_else.instruction else {
+                return Err(format!("Tried to jump to non-label {jmp_else}"));
+            };
+
+            out.push_str(&format!("\tcmpq $0, {}\n", locals.get_ref(cond)?));
+            out.push_str(&format!("\tjne .L{then_target}\n"));
+            out.push_str(&format!("\tjmp .L{else_target}\n"));
+        }
+        Label(name) => out.push_str(&format!(".L{name}:\n")),
     }
+    Ok(())
+}
 
-    out.push_str(
-        "
-\tmovq $0, %rax
-\tmovq %rbp, %rsp
-\tpopq %rbp
-\tret",
-    );
+// Assertions need the source location of the call, so they can't be plain function calls
+fn handle_assert(
+    out: &mut String,
+    locals: &Locals,
+    instruction: &IrInstruction,
+    cond: &IrVar,
+    assert_count: usize,
+) -> Result<(), String> {
+    let label = format!(".Lassert_passed{assert_count}");
+
+    out.push_str(&format!("\tcmpq $0, {}\n", locals.get_ref(cond)?));
+    out.push_str(&format!("\tjne {label}\n"));
+    out.push_str(&format!("\tmovq ${}, %rdi\n", instruction.loc.line()));
+    out.push_str(&format!("\tmovq ${}, %rsi\n", instruction.loc.char()));
 
-    out
+    // Align stack according to spec
+    if locals.stack_used % 16 != 0 {
+        out.push_str("\tsubq $8, %rsp\n");
+    }
+    out.push_str("\tcallq assert_fail\n");
+    out.push_str(&format!("{label}:\n"));
+    Ok(())
 }
 
 fn handle_intrinsics(
@@ -129,23 +210,28 @@ fn handle_intrinsics(
     op_var: &IrVar,
     arg_vec: &[IrVar],
     output_var: &IrVar,
-) {
+) -> Result<(), String> {
     let res = "%rax";
     match &*op_var.name {
         "unary_not" => {
-            let arg0 = locals.get_ref(&arg_vec[0]);
+            let arg0 = locals.get_ref(unary_arg(op_var, arg_vec)?)?;
             out.push_str(&format!("\tmovq {arg0}, {res}\n"));
             out.push_str(&format!("\txorq $1, {res}\n"));
         }
         "unary_-" => {
-            let arg0 = locals.get_ref(&arg_vec[0]);
+            let arg0 = locals.get_ref(unary_arg(op_var, arg_vec)?)?;
             out.push_str(&format!("\tmovq {arg0}, {res}\n"));
             out.push_str(&format!("\tnegq {res}\n"));
         }
         _ => {
             // Must be binary intrinsic
-            let arg0 = locals.get_ref(&arg_vec[0]);
-            let arg1 = locals.get_ref(&arg_vec[1]);
+            let [arg0, arg1] = arg_vec else {
+                return Err(format!(
+                    "Binary intrinsic {op_var} takes exactly two arguments!"
+                ));
+            };
+            let arg0 = locals.get_ref(arg0)?;
+            let arg1 = locals.get_ref(arg1)?;
             match &*op_var.name {
                 "+" => {
                     if arg0 != res {
@@ -187,9 +273,9 @@ fn handle_intrinsics(
                         "<=" => "setle",
                         ">" => "setg",
                         ">=" => "setge",
-                        "==" => "sete",
-                        "!=" => "setne",
-                        _ => panic!("Unknown intrinsic {op_var}!"),
+                        "==_int" | "==_bool" => "sete",
+                        "!=_int" | "!=_bool" => "setne",
+                        _ => return Err(format!("Unknown intrinsic {op_var}!")),
                     };
                     // We use 'al' below, which means the lower bytes of 'rax'
 
@@ -208,7 +294,17 @@ fn handle_intrinsics(
             }
         }
     }
-    out.push_str(&format!("\tmovq {res}, {}\n", locals.get_ref(output_var)));
+    out.push_str(&format!("\tmovq {res}, {}\n", locals.get_ref(output_var)?));
+    Ok(())
+}
+
+fn unary_arg<'a>(op_var: &IrVar, arg_vec: &'a [IrVar]) -> Result<&'a IrVar, String> {
+    match arg_vec {
+        [arg] => Ok(arg),
+        _ => Err(format!(
+            "Unary intrinsic {op_var} takes exactly one argument!"
+        )),
+    }
 }
 
 #[derive(Debug)]
@@ -235,10 +331,17 @@ impl Locals {
         }
     }
 
-    pub fn get_ref(&self, var: &IrVar) -> &str {
-        self.var_to_location.get(var).unwrap_or_else(|| {
-            panic!("Tried to use non-existant var '{var}' in assembly generation!")
-        })
+    // Globals are functions, which have no stack location and can only be called
+    pub fn get_ref(&self, var: &IrVar) -> Result<&str, String> {
+        match self.var_to_location.get(var) {
+            Some(location) => Ok(location),
+            None if IrVar::new_global_types().contains_key(var) => Err(format!(
+                "Function '{var}' can only be called, not used as a value!"
+            )),
+            None => Err(format!(
+                "Tried to use non-existant var '{var}' in assembly generation!"
+            )),
+        }
     }
 
     pub fn stack_used(&self) -> i64 {
@@ -1,26 +1,71 @@
-use crate::compiler::token::CodeLocation;
+use crate::compiler::token::{CodeLocation, Span};
 use crate::compiler::variable::Type;
 use std::fmt;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TypeExpression {
-    Int(CodeLocation),
-    Bool(CodeLocation),
+    Int(CodeLocation, Span),
+    Bool(CodeLocation, Span),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct AstNode<'source> {
     pub loc: CodeLocation,
+    pub span: Span,
     pub node_type: Type,
     pub expr: Expression<'source>,
+    // Symbol implementing an overloaded operator or function, chosen by the type checker
+    pub overload: Option<&'source str>,
+    // Levels in the tree under this node, counting the node itself
+    pub depth: usize,
 }
 
 impl<'source> AstNode<'source> {
-    pub fn new(loc: CodeLocation, expr: Expression<'source>) -> AstNode<'source> {
-        AstNode {
+    pub fn new(loc: CodeLocation, span: Span, expr: Expression<'source>) -> AstNode<'source> {
+        let mut node = AstNode {
             loc,
+            span,
             expr,
             node_type: Type::Unit,
+            overload: None,
+            depth: 1,
+        };
+        node.update_depth();
+        node
+    }
+
+    // Needed after the children are replaced
+    pub fn update_depth(&mut self) {
+        self.depth = 1 + self
+            .children()
+            .iter()
+            .map(|child| child.depth)
+            .max()
+            .unwrap_or(0);
+    }
+
+    // Direct subexpressions, in source order
+    pub fn children(&self) -> Vec<&AstNode<'source>> {
+        match &self.expr {
+            Expression::EmptyLiteral()
+            | Expression::IntLiteral(_)
+            | Expression::BoolLiteral(_)
+            | Expression::Identifier(_)
+            | Expression::Error(_) => vec![],
+            Expression::UnaryOp(_, expr)
+            | Expression::VarDeclaration(_, expr, _)
+            | Expression::Defer(expr)
+            | Expression::MacroDefinition(_, _, expr) => vec![expr],
+            Expression::BinaryOp(left, _, right) => vec![left, right],
+            Expression::Conditional(condition_expr, then_expr, else_expr) => {
+                let mut children = vec![&**condition_expr, &**then_expr];
+                children.extend(else_expr.as_deref());
+                children
+            }
+            Expression::While(condition_expr, do_expr) => vec![condition_expr, do_expr],
+            Expression::FunCall(_, expressions) | Expression::Block(expressions) => {
+                expressions.iter().collect()
+            }
         }
     }
 }
@@ -54,6 +99,10 @@ pub enum Expression<'source> {
     While(Box<AstNode<'source>>, Box<AstNode<'source>>),
     FunCall(&'source str, Vec<AstNode<'source>>),
     Block(Vec<AstNode<'source>>),
+    Defer(Box<AstNode<'source>>),
+    MacroDefinition(&'source str, Vec<&'source str>, Box<AstNode<'source>>),
+    // Placeholder for code that failed to parse, holding the error message
+    Error(String),
 }
 
 impl<'source> Expression<'source> {
@@ -70,6 +119,9 @@ impl<'source> Expression<'source> {
             Expression::While(..) => "While loop",
             Expression::FunCall(..) => "Function call",
             Expression::Block(..) => "Block",
+            Expression::Defer(..) => "Deferred expression",
+            Expression::MacroDefinition(..) => "Macro definition",
+            Expression::Error(..) => "Syntax error",
         }
     }
 
@@ -86,6 +138,11 @@ impl<'source> Expression<'source> {
             Expression::While(condition, _) => format!("while {:?}", condition),
             Expression::FunCall(name, args) => format!("{} with {} args", name, args.len()),
             Expression::Block(expressions) => format!("with {} expressions", expressions.len()),
+            Expression::Defer(expr) => format!("{:?}", expr),
+            Expression::MacroDefinition(name, params, _) => {
+                format!("{} with {} params", name, params.len())
+            }
+            Expression::Error(msg) => msg.to_string(),
         }
     }
 }
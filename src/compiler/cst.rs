@@ -0,0 +1,181 @@
+use std::fmt;
+
+use crate::compiler::{ast::AstNode, token::Token};
+
+// Concrete syntax tree, built on top of the AST from losslessly tokenized source. Every token
+// belongs to the innermost node whose span contains it, so walking the tree in order visits all
+// of the tokens, and with their trivia the exact input can be regenerated.
+#[derive(Debug)]
+pub struct Cst<'a, 'source> {
+    pub root: CstNode<'a, 'source>,
+    pub trailing_trivia: &'source str,
+}
+
+#[derive(Debug)]
+pub struct CstNode<'a, 'source> {
+    pub ast: &'a AstNode<'source>,
+    pub children: Vec<CstElement<'a, 'source>>,
+}
+
+#[derive(Debug)]
+pub enum CstElement<'a, 'source> {
+    Node(CstNode<'a, 'source>),
+    Token(&'a Token<'source>),
+}
+
+pub fn build_cst<'a, 'source>(
+    ast: &'a AstNode<'source>,
+    tokens: &'a [Token<'source>],
+    trailing_trivia: &'source str,
+) -> Cst<'a, 'source> {
+    let mut pos = 0;
+    let mut root = build_node(ast, tokens, &mut pos);
+
+    // Anything outside of the root span still has to be kept
+    root.children
+        .extend(tokens[pos..].iter().map(CstElement::Token));
+
+    Cst {
+        root,
+        trailing_trivia,
+    }
+}
+
+fn build_node<'a, 'source>(
+    ast: &'a AstNode<'source>,
+    tokens: &'a [Token<'source>],
+    pos: &mut usize,
+) -> CstNode<'a, 'source> {
+    let mut children = Vec::new();
+
+    let mut child_nodes = ast.children();
+    child_nodes.sort_by_key(|child| child.span.start);
+
+    for child in child_nodes {
+        while *pos < tokens.len() && tokens[*pos].span.start < child.span.start {
+            children.push(CstElement::Token(&tokens[*pos]));
+            *pos += 1;
+        }
+        children.push(CstElement::Node(build_node(child, tokens, pos)));
+    }
+
+    while *pos < tokens.len() && tokens[*pos].span.end <= ast.span.end {
+        children.push(CstElement::Token(&tokens[*pos]));
+        *pos += 1;
+    }
+
+    CstNode { ast, children }
+}
+
+impl<'a, 'source> CstNode<'a, 'source> {
+    // All tokens of the node, in source order
+    pub fn tokens(&self) -> Vec<&'a Token<'source>> {
+        self.children
+            .iter()
+            .flat_map(|child| match child {
+                CstElement::Node(node) => node.tokens(),
+                CstElement::Token(token) => vec![*token],
+            })
+            .collect()
+    }
+}
+
+impl<'a, 'source> Cst<'a, 'source> {
+    // Indented outline of the tree, one node or token per line
+    pub fn outline(&self) -> String {
+        let mut outline = String::new();
+        self.root.write_outline(&mut outline, 0);
+        outline
+    }
+}
+
+impl<'a, 'source> CstNode<'a, 'source> {
+    fn write_outline(&self, outline: &mut String, depth: usize) {
+        outline.push_str(&format!("{}{}\n", "  ".repeat(depth), self.ast));
+        for child in &self.children {
+            match child {
+                CstElement::Node(node) => node.write_outline(outline, depth + 1),
+                CstElement::Token(token) => {
+                    outline.push_str(&format!("{}{}\n", "  ".repeat(depth + 1), token))
+                }
+            }
+        }
+    }
+}
+
+impl<'a, 'source> fmt::Display for Cst<'a, 'source> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.root.tokens() {
+            write!(f, "{}{}", token.leading_trivia, token.text)?;
+        }
+        write!(f, "{}", self.trailing_trivia)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{parser::parse, tokenizer::tokenize_lossless};
+
+    fn round_trip(code: &str) -> String {
+        let (tokens, trailing_trivia) = tokenize_lossless(code).unwrap();
+        let ast = parse(&tokens).unwrap();
+        format!("{}", build_cst(&ast, &tokens, trailing_trivia))
+    }
+
+    #[test]
+    fn test_cst_round_trip() {
+        let programs = [
+            "1 + 2",
+            "  (1 + 2)   *3 // Comment\n",
+            "# Header\nvar x: Int = 1;\n\nwhile x < 10 do {\n\tx = x + 1; /* /* */ */\n}\n",
+            "macro double(x) { x + x };\ndouble( 2 ) ;",
+            "{ defer print(1); f(a,\n  b) }\n\n",
+            "if not true then { } else -1;",
+            "var ñandú = 1; ñandú",
+        ];
+
+        for code in programs {
+            assert_eq!(round_trip(code), code);
+        }
+    }
+
+    #[test]
+    fn test_cst_structure() {
+        let (tokens, trailing_trivia) = tokenize_lossless("(1) + f(2)").unwrap();
+        let ast = parse(&tokens).unwrap();
+        let cst = build_cst(&ast, &tokens, trailing_trivia);
+
+        fn texts<'source>(node: &CstNode<'_, 'source>) -> Vec<&'source str> {
+            node.tokens()
+                .iter()
+                .map(|token| token.text)
+                .collect::<Vec<_>>()
+        }
+        assert_eq!(
+            texts(&cst.root),
+            vec!["(", "1", ")", "+", "f", "(", "2", ")"]
+        );
+
+        let [CstElement::Node(left), CstElement::Token(op), CstElement::Node(right)] =
+            &cst.root.children[..]
+        else {
+            panic!("Expected a binary op with two nodes!");
+        };
+        assert_eq!(texts(left), vec!["(", "1", ")"]);
+        assert_eq!(op.text, "+");
+        assert_eq!(texts(right), vec!["f", "(", "2", ")"]);
+    }
+
+    #[test]
+    fn test_cst_outline() {
+        let (tokens, trailing_trivia) = tokenize_lossless("-a").unwrap();
+        let ast = parse(&tokens).unwrap();
+        let cst = build_cst(&ast, &tokens, trailing_trivia);
+
+        assert_eq!(
+            cst.outline(),
+            "Unary operation - at 1:1\n  Operator ``-`` at 1:1\n  Identifier a at 1:2\n    Identifier ``a`` at 1:2\n"
+        );
+    }
+}
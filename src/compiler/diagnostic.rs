@@ -0,0 +1,398 @@
+use std::{error::Error, fmt::Display};
+
+use json::{object, JsonValue};
+
+use crate::compiler::{
+    error_codes::ErrorCode,
+    token::{CodeLocation, Span},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub loc: CodeLocation,
+    pub span: Span,
+    pub msg: String,
+}
+
+// A problem found in the source, pointing at the code it is about. The primary label is where
+// the problem is, secondary labels point at related code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<ErrorCode>,
+    pub msg: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(msg: String, loc: CodeLocation, span: Span) -> Self {
+        Self::new(Severity::Error, msg, loc, span)
+    }
+
+    pub fn warning(msg: String, loc: CodeLocation, span: Span) -> Self {
+        Self::new(Severity::Warning, msg, loc, span)
+    }
+
+    fn new(severity: Severity, msg: String, loc: CodeLocation, span: Span) -> Self {
+        Self {
+            severity,
+            code: None,
+            msg,
+            primary: Label {
+                loc,
+                span,
+                msg: String::new(),
+            },
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label(mut self, loc: CodeLocation, span: Span, msg: String) -> Self {
+        self.secondary.push(Label { loc, span, msg });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    // Prints the message followed by every line with a label, with the primary label underlined
+    // by carets and secondary labels by dashes:
+    //
+    // error[E0103]: Mismatched types in if-then-else branches: expected Int, found Bool
+    //  --> 1:1
+    //   |
+    // 1 | if true then 1 else false
+    //   | ^^^^^^^^^^^^^^^^^^^^^^^^^
+    //   |              - Expected Int because of this branch
+    //   |                     ----- Found Bool
+    pub fn render(&self, source: &str) -> String {
+        let mut labels = vec![(&self.primary, '^')];
+        labels.extend(self.secondary.iter().map(|label| (label, '-')));
+
+        let mut lines = labels
+            .iter()
+            .filter_map(|(label, mark)| Snippet::new(source, label, *mark))
+            .collect::<Vec<_>>();
+        lines.sort_by_key(|snippet| snippet.line);
+
+        let gutter_width = lines
+            .iter()
+            .map(|snippet| snippet.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let gutter = " ".repeat(gutter_width);
+
+        let mut rendered = format!("{}: {}\n", self.heading(), self.msg);
+        rendered += &format!("{}--> {}\n", gutter, self.primary.loc);
+
+        if !lines.is_empty() {
+            rendered += &format!("{} |\n", gutter);
+        }
+        // Labels on the same line share the source line, each underlined on its own row
+        let mut previous_line = None;
+        for snippet in &lines {
+            if previous_line != Some(snippet.line) {
+                rendered += &format!("{:>gutter_width$} | {}\n", snippet.line, snippet.text);
+                previous_line = Some(snippet.line);
+            }
+
+            let underline = snippet.mark.to_string().repeat(snippet.width);
+            let row = format!("{}{} {}", snippet.indent, underline, snippet.msg);
+            rendered += &format!("{} | {}\n", gutter, row.trim_end());
+        }
+
+        for note in &self.notes {
+            rendered += &format!("{} = note: {}\n", gutter, note);
+        }
+
+        rendered
+    }
+
+    // A JSON object for tools reading the output. Positions are 1-based lines and columns counted
+    // in characters, taken from the spans with the end exclusive.
+    pub fn to_json(&self, source: &str, file: &str) -> JsonValue {
+        let mut value = object! {
+            code: self.code.map(|code| code.code()),
+            severity: self.severity.to_string(),
+            message: self.msg.as_str(),
+        };
+        for (key, field) in label_json(&self.primary, source, file).entries() {
+            if key != "message" {
+                value[key] = field.clone();
+            }
+        }
+        value["related"] = self
+            .secondary
+            .iter()
+            .map(|label| label_json(label, source, file))
+            .collect::<Vec<_>>()
+            .into();
+        value["notes"] = self.notes.clone().into();
+        value
+    }
+
+    // The severity, followed by the error code when there is one
+    fn heading(&self) -> String {
+        match self.code {
+            Some(code) => format!("{}[{}]", self.severity, code),
+            None => self.severity.to_string(),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} at {}",
+            self.heading(),
+            self.msg,
+            self.primary.loc
+        )
+    }
+}
+
+impl Error for Diagnostic {}
+
+fn label_json(label: &Label, source: &str, file: &str) -> JsonValue {
+    let (line_start, column_start) = position(source, label.span.start);
+    let (line_end, column_end) = position(source, label.span.end);
+
+    object! {
+        message: label.msg.as_str(),
+        file: file,
+        line_start: line_start,
+        column_start: column_start,
+        line_end: line_end,
+        column_end: column_end,
+    }
+}
+
+// Line and column of a byte offset, clamped to the source
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    (
+        source[..line_start].matches('\n').count() + 1,
+        source[line_start..offset].chars().count() + 1,
+    )
+}
+
+// A label resolved to the source line it starts on. Labels spanning several lines are only
+// underlined up to the end of their first line.
+struct Snippet<'a> {
+    line: usize,
+    text: &'a str,
+    // Whitespace up to the label, keeping tabs so the underline lines up with the source
+    indent: String,
+    width: usize,
+    mark: char,
+    msg: &'a str,
+}
+
+impl<'a> Snippet<'a> {
+    fn new(source: &'a str, label: &'a Label, mark: char) -> Option<Self> {
+        let Span { start, end } = label.span;
+        if start > source.len() || !source.is_char_boundary(start) {
+            return None;
+        }
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let end = end.clamp(start, line_end);
+        let end = (end..=line_end)
+            .find(|end| source.is_char_boundary(*end))
+            .unwrap_or(line_end);
+
+        let indent = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        Some(Self {
+            line: source[..line_start].matches('\n').count() + 1,
+            text: source[line_start..line_end].trim_end_matches('\r'),
+            indent,
+            width: source[start..end].chars().count().max(1),
+            mark,
+            msg: &label.msg,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_primary() {
+        let diagnostic = Diagnostic::error(
+            String::from("Non-bool as while-do condition!"),
+            CodeLocation::new(1, 7),
+            Span::new(6, 7),
+        );
+        assert_eq!(
+            diagnostic.render("while 1 do 2"),
+            "error: Non-bool as while-do condition!\n \
+            --> 1:7\n  \
+              |\n\
+            1 | while 1 do 2\n  \
+              |       ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_secondary_and_notes() {
+        let diagnostic = Diagnostic::error(
+            String::from("Mismatched types"),
+            CodeLocation::new(2, 3),
+            Span::new(10, 18),
+        )
+        .with_label(
+            CodeLocation::new(1, 5),
+            Span::new(4, 5),
+            String::from("Int"),
+        )
+        .with_label(
+            CodeLocation::new(2, 5),
+            Span::new(14, 18),
+            String::from("Bool"),
+        )
+        .with_note(String::from("a was declared as Int"))
+        .with_code(ErrorCode::MismatchedAssignment);
+
+        assert_eq!(
+            diagnostic.render("var a = 1\na = true"),
+            "error[E0102]: Mismatched types\n \
+            --> 2:3\n  \
+              |\n\
+            1 | var a = 1\n  \
+              |     - Int\n\
+            2 | a = true\n  \
+              | ^^^^^^^^\n  \
+              |     ---- Bool\n  \
+              = note: a was declared as Int\n"
+        );
+    }
+
+    #[test]
+    fn test_render_line_numbers_aligned() {
+        let source = "1;\n".repeat(9) + "foo";
+        let diagnostic = Diagnostic::error(
+            String::from("No symbol foo found!"),
+            CodeLocation::new(10, 1),
+            Span::new(27, 30),
+        )
+        .with_label(CodeLocation::new(1, 1), Span::new(0, 1), String::new());
+
+        assert_eq!(
+            diagnostic.render(&source),
+            "error: No symbol foo found!\n  \
+            --> 10:1\n   \
+               |\n \
+             1 | 1;\n   \
+               | -\n\
+            10 | foo\n   \
+               | ^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_multiline_span() {
+        let diagnostic = Diagnostic::error(
+            String::from("Mismatched return types in if-then-else!"),
+            CodeLocation::new(1, 1),
+            Span::new(0, 21),
+        );
+        assert_eq!(
+            diagnostic.render("if true\nthen 1 else false"),
+            "error: Mismatched return types in if-then-else!\n \
+            --> 1:1\n  \
+              |\n\
+            1 | if true\n  \
+              | ^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_tabs_and_unicode() {
+        let diagnostic = Diagnostic::error(
+            String::from("No symbol b found!"),
+            CodeLocation::new(1, 5),
+            Span::new(6, 7),
+        );
+        assert_eq!(
+            diagnostic.render("\tä + b"),
+            "error: No symbol b found!\n \
+            --> 1:5\n  \
+              |\n\
+            1 | \tä + b\n  \
+              | \t    ^\n"
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let diagnostic = Diagnostic::error(
+            String::from("Mismatched types"),
+            CodeLocation::new(2, 3),
+            Span::new(11, 19),
+        )
+        .with_code(ErrorCode::MismatchedAssignment)
+        .with_label(
+            CodeLocation::new(1, 5),
+            Span::new(4, 6),
+            String::from("Int"),
+        )
+        .with_note(String::from("a was declared as Int"));
+
+        assert_eq!(
+            diagnostic.to_json("var ä = 1\na = true", "main.src").dump(),
+            "{\"code\":\"E0102\",\"severity\":\"error\",\"message\":\"Mismatched types\",\
+             \"file\":\"main.src\",\"line_start\":2,\"column_start\":1,\"line_end\":2,\
+             \"column_end\":9,\"related\":[{\"message\":\"Int\",\"file\":\"main.src\",\
+             \"line_start\":1,\"column_start\":5,\"line_end\":1,\"column_end\":6}],\
+             \"notes\":[\"a was declared as Int\"]}"
+        );
+
+        let diagnostic = Diagnostic::warning(
+            String::from("Unused variable"),
+            CodeLocation::new(1, 1),
+            Span::new(0, 3),
+        );
+        let value = diagnostic.to_json("abc", "-");
+        assert!(value["code"].is_null());
+        assert_eq!(value["severity"], "warning");
+    }
+}
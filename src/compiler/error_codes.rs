@@ -0,0 +1,377 @@
+use std::fmt::Display;
+
+// Every error about the compiled program has a code that keeps its meaning when the message is
+// reworded, so scripts can match on the code instead of the message. Codes are grouped by the
+// pass that reports them: E00xx tokenizer, E01xx type checker, E02xx parser, E03xx macro
+// expander and E04xx symbol table. Codes are never reused, even if an error is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidToken,
+    UnterminatedComment,
+    IntegerOutOfRange,
+    MismatchedAssignment,
+    MismatchedBranches,
+    NonBoolCondition,
+    MismatchedDeclaration,
+    MismatchedArguments,
+    NoMatchingOverload,
+    NotAFunction,
+    InvalidAssignmentTarget,
+    UnexpandedMacro,
+    OverloadedValue,
+    ArgumentCount,
+    FunctionValue,
+    UnexpectedToken,
+    ExpectedToken,
+    EmptyInput,
+    NestingTooDeep,
+    InvalidIntegerLiteral,
+    KeywordAsName,
+    MisplacedStatement,
+    InvalidTypeSpecifier,
+    ChainedNonAssociative,
+    InvalidPrecedence,
+    OperatorRedeclared,
+    MacroTooDeep,
+    MacroArgumentCount,
+    MacroRedefined,
+    UnknownSymbol,
+    AlreadyDefined,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 31] = [
+        ErrorCode::InvalidToken,
+        ErrorCode::UnterminatedComment,
+        ErrorCode::IntegerOutOfRange,
+        ErrorCode::MismatchedAssignment,
+        ErrorCode::MismatchedBranches,
+        ErrorCode::NonBoolCondition,
+        ErrorCode::MismatchedDeclaration,
+        ErrorCode::MismatchedArguments,
+        ErrorCode::NoMatchingOverload,
+        ErrorCode::NotAFunction,
+        ErrorCode::InvalidAssignmentTarget,
+        ErrorCode::UnexpandedMacro,
+        ErrorCode::OverloadedValue,
+        ErrorCode::ArgumentCount,
+        ErrorCode::FunctionValue,
+        ErrorCode::UnexpectedToken,
+        ErrorCode::ExpectedToken,
+        ErrorCode::EmptyInput,
+        ErrorCode::NestingTooDeep,
+        ErrorCode::InvalidIntegerLiteral,
+        ErrorCode::KeywordAsName,
+        ErrorCode::MisplacedStatement,
+        ErrorCode::InvalidTypeSpecifier,
+        ErrorCode::ChainedNonAssociative,
+        ErrorCode::InvalidPrecedence,
+        ErrorCode::OperatorRedeclared,
+        ErrorCode::MacroTooDeep,
+        ErrorCode::MacroArgumentCount,
+        ErrorCode::MacroRedefined,
+        ErrorCode::UnknownSymbol,
+        ErrorCode::AlreadyDefined,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidToken => "E0001",
+            ErrorCode::UnterminatedComment => "E0002",
+            ErrorCode::IntegerOutOfRange => "E0101",
+            ErrorCode::MismatchedAssignment => "E0102",
+            ErrorCode::MismatchedBranches => "E0103",
+            ErrorCode::NonBoolCondition => "E0104",
+            ErrorCode::MismatchedDeclaration => "E0105",
+            ErrorCode::MismatchedArguments => "E0106",
+            ErrorCode::NoMatchingOverload => "E0107",
+            ErrorCode::NotAFunction => "E0108",
+            ErrorCode::InvalidAssignmentTarget => "E0109",
+            ErrorCode::UnexpandedMacro => "E0110",
+            ErrorCode::OverloadedValue => "E0111",
+            ErrorCode::ArgumentCount => "E0112",
+            ErrorCode::FunctionValue => "E0113",
+            ErrorCode::UnexpectedToken => "E0201",
+            ErrorCode::ExpectedToken => "E0202",
+            ErrorCode::EmptyInput => "E0203",
+            ErrorCode::NestingTooDeep => "E0204",
+            ErrorCode::InvalidIntegerLiteral => "E0205",
+            ErrorCode::KeywordAsName => "E0206",
+            ErrorCode::MisplacedStatement => "E0207",
+            ErrorCode::InvalidTypeSpecifier => "E0208",
+            ErrorCode::ChainedNonAssociative => "E0209",
+            ErrorCode::InvalidPrecedence => "E0210",
+            ErrorCode::OperatorRedeclared => "E0211",
+            ErrorCode::MacroTooDeep => "E0301",
+            ErrorCode::MacroArgumentCount => "E0302",
+            ErrorCode::MacroRedefined => "E0303",
+            ErrorCode::UnknownSymbol => "E0401",
+            ErrorCode::AlreadyDefined => "E0402",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL
+            .into_iter()
+            .find(|error_code| error_code.code().eq_ignore_ascii_case(code))
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidToken => "invalid token",
+            ErrorCode::UnterminatedComment => "unterminated comment",
+            ErrorCode::IntegerOutOfRange => "integer out of range",
+            ErrorCode::MismatchedAssignment => "mismatched types in assignment",
+            ErrorCode::MismatchedBranches => "mismatched branch types",
+            ErrorCode::NonBoolCondition => "non-bool condition",
+            ErrorCode::MismatchedDeclaration => "mismatched declared type",
+            ErrorCode::MismatchedArguments => "mismatched argument types",
+            ErrorCode::NoMatchingOverload => "no matching overload",
+            ErrorCode::NotAFunction => "call of a non-function",
+            ErrorCode::InvalidAssignmentTarget => "invalid assignment target",
+            ErrorCode::UnexpandedMacro => "unexpanded macro",
+            ErrorCode::OverloadedValue => "overloaded function used as a value",
+            ErrorCode::ArgumentCount => "wrong number of arguments",
+            ErrorCode::FunctionValue => "function used as a value",
+            ErrorCode::UnexpectedToken => "unexpected token",
+            ErrorCode::ExpectedToken => "missing expected token",
+            ErrorCode::EmptyInput => "empty program",
+            ErrorCode::NestingTooDeep => "nesting too deep",
+            ErrorCode::InvalidIntegerLiteral => "invalid integer literal",
+            ErrorCode::KeywordAsName => "keyword used as a name",
+            ErrorCode::MisplacedStatement => "misplaced statement",
+            ErrorCode::InvalidTypeSpecifier => "invalid type specifier",
+            ErrorCode::ChainedNonAssociative => "chained non-associative operator",
+            ErrorCode::InvalidPrecedence => "invalid operator precedence",
+            ErrorCode::OperatorRedeclared => "operator redeclared",
+            ErrorCode::MacroTooDeep => "macro expansion too deep",
+            ErrorCode::MacroArgumentCount => "wrong number of macro arguments",
+            ErrorCode::MacroRedefined => "macro redefined",
+            ErrorCode::UnknownSymbol => "unknown symbol",
+            ErrorCode::AlreadyDefined => "variable already defined",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidToken => {
+                "The program contains a character that doesn't start any token of the language."
+            }
+            ErrorCode::UnterminatedComment => {
+                "A block comment was opened with /* but never closed with */."
+            }
+            ErrorCode::IntegerOutOfRange => {
+                "An integer literal doesn't fit in the 64-bit signed Int type."
+            }
+            ErrorCode::MismatchedAssignment => {
+                "The value assigned to a variable has a different type than the variable. A \
+                 variable keeps the type it was declared with."
+            }
+            ErrorCode::MismatchedBranches => {
+                "The then and else branches of an if-then-else have different types. The value of \
+                 the whole expression comes from either branch, so both must have the same type."
+            }
+            ErrorCode::NonBoolCondition => {
+                "The condition of an if-then-else or a while loop isn't a Bool. There are no \
+                 implicit conversions, so compare the value instead."
+            }
+            ErrorCode::MismatchedDeclaration => {
+                "A variable declared with a type is initialized with a value of another type."
+            }
+            ErrorCode::MismatchedArguments => {
+                "The arguments of a function or an operator don't have the types it takes."
+            }
+            ErrorCode::NoMatchingOverload => {
+                "None of the overloads of a function or an operator take arguments of the given \
+                 types. The overloads that exist are listed in the notes of the error."
+            }
+            ErrorCode::NotAFunction => "A variable that isn't a function is called like one.",
+            ErrorCode::InvalidAssignmentTarget => {
+                "Only variables can be assigned to. The left side of = is some other expression."
+            }
+            ErrorCode::UnexpandedMacro => {
+                "A macro definition reached the type checker. Macros are expanded before type \
+                 checking, so this is a bug in the compiler rather than in the program."
+            }
+            ErrorCode::OverloadedValue => {
+                "A function with several overloads, like print, is used as a value. Which overload \
+                 is meant is only known from the arguments of a call."
+            }
+            ErrorCode::ArgumentCount => {
+                "A function is called with a different number of arguments than it takes."
+            }
+            ErrorCode::FunctionValue => {
+                "A function is used as a value, like stored in a variable or passed as an \
+                 argument. Functions can only be called."
+            }
+            ErrorCode::UnexpectedToken => {
+                "A token appears where no expression can start or continue."
+            }
+            ErrorCode::ExpectedToken => {
+                "A token required by the syntax is missing, like the semicolon between two \
+                 expressions or the closing brace of a block."
+            }
+            ErrorCode::EmptyInput => {
+                "The program is empty, or only contains whitespace and comments."
+            }
+            ErrorCode::NestingTooDeep => {
+                "Expressions are nested deeper than the compiler allows, by default 256 levels \
+                 of parentheses, blocks or operands. Very long chains of operators, or macros \
+                 expanding into deeply nested code, can also exceed the 2048 levels allowed in \
+                 the syntax tree. Split the expression up using variables."
+            }
+            ErrorCode::InvalidIntegerLiteral => {
+                "An integer literal has no digits after its prefix, ends in an underscore or has \
+                 two in a row, or is too large to represent at all."
+            }
+            ErrorCode::KeywordAsName => {
+                "A reserved keyword is used as the name of a variable, macro or function."
+            }
+            ErrorCode::MisplacedStatement => {
+                "A variable declaration, defer, macro definition or operator declaration appears \
+                 where only an expression is allowed. These are only allowed directly in a block \
+                 or at the top level of the program, and defer only inside a block."
+            }
+            ErrorCode::InvalidTypeSpecifier => {
+                "The type in a variable declaration isn't a type the language has. The types that \
+                 can be written are Int and Bool."
+            }
+            ErrorCode::ChainedNonAssociative => {
+                "An operator declared with infix has no associativity, so it can't be chained \
+                 with another operator of the same precedence without parentheses."
+            }
+            ErrorCode::InvalidPrecedence => {
+                "A declared operator has a precedence outside the range reserved for declared \
+                 operators."
+            }
+            ErrorCode::OperatorRedeclared => {
+                "An operator that is built in, or was already declared, is declared again."
+            }
+            ErrorCode::MacroTooDeep => {
+                "Expanding a macro produced more nested macro uses than the maximum of 64. This \
+                 usually means a macro uses itself, expanding forever."
+            }
+            ErrorCode::MacroArgumentCount => {
+                "A macro is used with a different number of arguments than it has parameters."
+            }
+            ErrorCode::MacroRedefined => {
+                "A macro with the same name is already defined in the same scope."
+            }
+            ErrorCode::UnknownSymbol => {
+                "A name is used that isn't defined in any enclosing scope. Variables can only be \
+                 used after their declaration, and only inside the block declaring them."
+            }
+            ErrorCode::AlreadyDefined => {
+                "A variable is declared twice in the same scope. Assign to the existing variable \
+                 instead, or declare the new one in an inner block."
+            }
+        }
+    }
+
+    // A program that fails with the error, when one can be written out
+    fn example(&self) -> Option<&'static str> {
+        match self {
+            ErrorCode::InvalidToken => Some("var price = 5 $"),
+            ErrorCode::UnterminatedComment => Some("1 + /* two\n2"),
+            ErrorCode::IntegerOutOfRange => Some("9223372036854775808"),
+            ErrorCode::MismatchedAssignment => Some("var a = 1;\na = true"),
+            ErrorCode::MismatchedBranches => Some("if true then 1 else false"),
+            ErrorCode::NonBoolCondition => Some("var a = 1;\nwhile a do a = a - 1"),
+            ErrorCode::MismatchedDeclaration => Some("var a: Bool = 1"),
+            ErrorCode::MismatchedArguments => Some("print_int(true)"),
+            ErrorCode::NoMatchingOverload => Some("print(print_int(1))"),
+            ErrorCode::NotAFunction => Some("var a = 1;\na(2)"),
+            ErrorCode::InvalidAssignmentTarget => Some("var a = 1;\n(a + 1) = 2"),
+            ErrorCode::UnexpandedMacro => None,
+            ErrorCode::OverloadedValue => Some("var show = print;\nshow(1)"),
+            ErrorCode::ArgumentCount => Some("print_int(1, 2)"),
+            ErrorCode::FunctionValue => Some("var show = print_int;\nshow(1)"),
+            ErrorCode::UnexpectedToken => Some("1 + * 2"),
+            ErrorCode::ExpectedToken => Some("var a = 1\nvar b = 2"),
+            ErrorCode::EmptyInput => Some("// Nothing to see here"),
+            ErrorCode::NestingTooDeep => None,
+            ErrorCode::InvalidIntegerLiteral => Some("0x_"),
+            ErrorCode::KeywordAsName => Some("var while = 1"),
+            ErrorCode::MisplacedStatement => Some("1 + var a = 2"),
+            ErrorCode::InvalidTypeSpecifier => Some("var a: Unit = {}"),
+            ErrorCode::ChainedNonAssociative => Some("infix 5 <=> = f;\n1 <=> 2 <=> 3"),
+            ErrorCode::InvalidPrecedence => Some("infixl 100 <+> = f"),
+            ErrorCode::OperatorRedeclared => Some("infixl 7 + = f"),
+            ErrorCode::MacroTooDeep => Some("macro forever(x) { forever(x) };\nforever(1)"),
+            ErrorCode::MacroArgumentCount => Some("macro double(x) { x + x };\ndouble(1, 2)"),
+            ErrorCode::MacroRedefined => Some("macro one() { 1 };\nmacro one() { 2 }"),
+            ErrorCode::UnknownSymbol => Some("{ var a = 1 };\na"),
+            ErrorCode::AlreadyDefined => Some("var a = 1;\nvar a = 2"),
+        }
+    }
+
+    // The long-form explanation printed by --explain
+    pub fn explain(&self) -> String {
+        let mut explanation = format!("{}: {}\n\n{}\n", self, self.title(), self.description());
+
+        if let Some(example) = self.example() {
+            explanation += "\nErroneous example:\n\n";
+            for line in example.lines() {
+                explanation += &format!("    {}\n", line);
+            }
+        }
+
+        explanation
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{compile, CompilerOptions};
+
+    #[test]
+    fn test_codes_unique() {
+        for (i, error_code) in ErrorCode::ALL.iter().enumerate() {
+            assert_eq!(*error_code as usize, i);
+            assert_eq!(ErrorCode::from_code(error_code.code()), Some(*error_code));
+            assert!(ErrorCode::ALL[..i]
+                .iter()
+                .all(|other| other.code() != error_code.code()));
+        }
+        assert_eq!(
+            ErrorCode::from_code("e0103"),
+            Some(ErrorCode::MismatchedBranches)
+        );
+        assert_eq!(ErrorCode::from_code("E9999"), None);
+    }
+
+    #[test]
+    fn test_examples_report_their_code() {
+        for error_code in ErrorCode::ALL {
+            let Some(example) = error_code.example() else {
+                continue;
+            };
+            let errors = compile(example, &CompilerOptions::default()).unwrap_err();
+            assert_eq!(
+                errors[0].code,
+                Some(error_code),
+                "example of {error_code} reported {:?}",
+                errors[0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        assert_eq!(
+            ErrorCode::MismatchedBranches.explain(),
+            "E0103: mismatched branch types\n\n\
+             The then and else branches of an if-then-else have different types. The value of \
+             the whole expression comes from either branch, so both must have the same type.\n\n\
+             Erroneous example:\n\n    \
+                 if true then 1 else false\n"
+        );
+    }
+}
@@ -1,138 +1,349 @@
+use std::{error::Error, fmt::Display};
+
 use crate::compiler::{
     ast::{
         AstNode,
         Expression::{self, *},
     },
+    diagnostic::Diagnostic,
     symtab::SymTab,
-    variable::Value,
+    token::{CodeLocation, Span},
+    variable::{Stop, Value},
 };
 
+#[derive(Debug)]
+pub struct RuntimeError {
+    msg: String,
+    loc: CodeLocation,
+    span: Span,
+    // Calls and deferred expressions the error happened within, innermost first
+    trace: Vec<String>,
+    // Set when the program called exit rather than failing, leaving it to the caller to end
+    pub exit_code: Option<i32>,
+}
+
+impl RuntimeError {
+    fn at(msg: String, ast: &AstNode) -> Self {
+        Self {
+            msg,
+            loc: ast.loc,
+            span: ast.span,
+            trace: Vec::new(),
+            exit_code: None,
+        }
+    }
+
+    fn stopped(stop: Stop, ast: &AstNode) -> Self {
+        match stop {
+            Stop::Error(msg) => Self::at(msg, ast),
+            Stop::Exit(code) => Self {
+                exit_code: Some(code),
+                ..Self::at(format!("Exited with code {}", code), ast)
+            },
+        }
+    }
+
+    fn within(mut self, frame: String) -> Self {
+        self.trace.push(frame);
+        self
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RuntimeError: {} at {}", self.msg, self.loc)?;
+        for frame in &self.trace {
+            write!(f, "\n    {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RuntimeError {}
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(error: RuntimeError) -> Self {
+        error.trace.into_iter().fold(
+            Diagnostic::error(error.msg, error.loc, error.span),
+            Diagnostic::with_note,
+        )
+    }
+}
+
 // Function was made as an exercise mid-way through the project and has been left mostly as-is
 // since!
 
-pub fn interpret<'source>(ast: &AstNode<'source>, symbols: &mut SymTab<'source, Value>) -> Value {
-    match &ast.expr {
+pub fn interpret<'source>(
+    ast: &AstNode<'source>,
+    symbols: &mut SymTab<'source, Value>,
+) -> Result<Value, RuntimeError> {
+    let located = |msg| RuntimeError::at(msg, ast);
+    let stopped = |stop| RuntimeError::stopped(stop, ast);
+    let get_fn = |symbols: &mut SymTab<'source, Value>, name: &str| match symbols.get(name) {
+        Ok(Value::Func(function)) => Ok(*function),
+        Ok(_) => Err(located(format!("{} is not a function!", name))),
+        Err(error) => Err(located(error.msg().to_string())),
+    };
+    let get_bool = |val, context: &str| match val {
+        Value::Bool(val) => Ok(val),
+        _ => Err(located(format!("Non-bool {}!", context))),
+    };
+
+    Ok(match &ast.expr {
         EmptyLiteral() => Value::None(),
         IntLiteral(val) => Value::Int(*val),
         BoolLiteral(val) => Value::Bool(*val),
-        Identifier(name) => *symbols.get(name).unwrap(),
+        Identifier(name) => *symbols
+            .get(name)
+            .map_err(|error| located(error.msg().to_string()))?,
         UnaryOp(op, expr) => {
-            let Value::Func(op_fn) = symbols.get(&format!("unary_{op}")).unwrap() else {
-                panic!("Operator {} does not correspond to a function!", op);
-            };
-            op_fn(&[interpret(expr, symbols)])
+            let op_fn = get_fn(symbols, &format!("unary_{op}"))?;
+            op_fn(&[interpret(expr, symbols)?]).map_err(stopped)?
         }
         BinaryOp(left, op, right) => match *op {
             "and" => {
-                let left_val = interpret(left, symbols);
-                if let Value::Bool(left_val) = left_val {
-                    if !left_val {
-                        Value::Bool(false)
-                    } else {
-                        let right_val = interpret(right, symbols);
-                        if let Value::Bool(right_val) = right_val {
-                            Value::Bool(right_val)
-                        } else {
-                            panic!("Non-bool with and operator");
-                        }
-                    }
+                let left_val = get_bool(interpret(left, symbols)?, "with and operator")?;
+                if !left_val {
+                    Value::Bool(false)
                 } else {
-                    panic!("Non-bool with and operator");
+                    let right_val = get_bool(interpret(right, symbols)?, "with and operator")?;
+                    Value::Bool(right_val)
                 }
             }
             "or" => {
-                let left_val = interpret(left, symbols);
-                if let Value::Bool(left_val) = left_val {
-                    if left_val {
-                        Value::Bool(true)
-                    } else {
-                        let right_val = interpret(right, symbols);
-                        if let Value::Bool(right_val) = right_val {
-                            Value::Bool(right_val)
-                        } else {
-                            panic!("Non-bool with or operator");
-                        }
-                    }
+                let left_val = get_bool(interpret(left, symbols)?, "with or operator")?;
+                if left_val {
+                    Value::Bool(true)
                 } else {
-                    panic!("Non-bool with or operator");
+                    let right_val = get_bool(interpret(right, symbols)?, "with or operator")?;
+                    Value::Bool(right_val)
                 }
             }
             "=" => {
                 if let Expression::Identifier(name) = left.expr {
-                    let val = interpret(right, symbols);
-                    *symbols.get(name).unwrap() = val;
+                    let val = interpret(right, symbols)?;
+                    *symbols
+                        .get(name)
+                        .map_err(|error| RuntimeError::at(error.msg().to_string(), left))? = val;
                     val
                 } else {
-                    panic!("Assignment must have identifier as left expr!");
+                    return Err(located(String::from(
+                        "Assignment must have identifier as left expr!",
+                    )));
                 }
             }
             _ => {
-                let Value::Func(op_fn) = symbols.get(op).unwrap() else {
-                    panic!("Operator {} does not correspond to a function!", op);
-                };
-                op_fn(&[interpret(left, symbols), interpret(right, symbols)])
+                let op_fn = get_fn(symbols, op)?;
+                op_fn(&[interpret(left, symbols)?, interpret(right, symbols)?]).map_err(stopped)?
             }
         },
         VarDeclaration(name, expr, _) => {
-            let val = interpret(expr, symbols);
-            symbols.insert(name, val).unwrap();
+            let val = interpret(expr, symbols)?;
+            symbols
+                .insert(name, val)
+                .map_err(|error| located(error.msg().to_string()))?;
             Value::None()
         }
         Conditional(condition_expr, then_expr, else_expr) => {
-            let Value::Bool(condition) = interpret(condition_expr, symbols) else {
-                panic!("Non-bool as if-then-else condition!");
-            };
+            let condition = get_bool(
+                interpret(condition_expr, symbols)?,
+                "as if-then-else condition",
+            )?;
 
             if let Some(else_expr) = else_expr {
                 if condition {
-                    interpret(then_expr, symbols)
+                    interpret(then_expr, symbols)?
                 } else {
-                    interpret(else_expr, symbols)
+                    interpret(else_expr, symbols)?
                 }
             } else {
                 if condition {
-                    interpret(then_expr, symbols);
+                    interpret(then_expr, symbols)?;
                 }
                 Value::None()
             }
         }
         While(condition, do_expr) => {
-            loop {
-                let condition = interpret(condition, symbols);
-                if let Value::Bool(cond) = condition {
-                    if cond {
-                        interpret(do_expr, symbols);
-                    } else {
-                        break;
-                    }
-                } else {
-                    panic!("Non-boon as while-do condition!");
-                }
+            while get_bool(interpret(condition, symbols)?, "as while-do condition")? {
+                interpret(do_expr, symbols)?;
             }
             Value::None()
         }
         FunCall(name, args) => {
             let mut arg_values = Vec::new();
             for arg in args {
-                arg_values.push(interpret(arg, symbols));
+                let val = interpret(arg, symbols)
+                    .map_err(|error| error.within(format!("in call to {} at {}", name, ast.loc)))?;
+                arg_values.push(val);
             }
 
-            let Value::Func(function) = symbols.get(name).unwrap() else {
-                panic!("Identifier {} does not correspond to a function!", name);
-            };
-
-            function(&arg_values)
+            let function = get_fn(symbols, name)?;
+            function(&arg_values).map_err(stopped)?
         }
         Block(expressions) => {
             symbols.push_level();
 
             let mut val = Value::None();
+            let mut deferred = Vec::new();
             for expression in expressions {
-                val = interpret(expression, symbols);
+                if let Defer(deferred_expr) = &expression.expr {
+                    deferred.push((deferred_expr, expression.loc));
+                    val = Value::None();
+                } else {
+                    val = interpret(expression, symbols)?;
+                }
+            }
+
+            // Deferred expressions run in reverse order when the block is exited
+            for (deferred_expr, loc) in deferred.into_iter().rev() {
+                interpret(deferred_expr, symbols)
+                    .map_err(|error| error.within(format!("in expression deferred at {}", loc)))?;
             }
 
             symbols.remove_level();
             val
         }
+        // Only reachable outside of a block, which the parser does not allow
+        Defer(_) => Value::None(),
+        // Macro definitions are removed by macro expansion
+        MacroDefinition(..) => Value::None(),
+        Error(msg) => {
+            return Err(located(format!(
+                "Syntax error in interpreted code: {}",
+                msg
+            )))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{parser::parse, tokenizer::tokenize, variable::set_program_args};
+
+    fn run(code: &str) -> Result<Value, RuntimeError> {
+        interpret(
+            &parse(&tokenize(code).unwrap()).unwrap(),
+            &mut SymTab::new_val_table(),
+        )
+    }
+
+    #[test]
+    fn test_assert() {
+        let result = run("assert(1 < 2); 3").unwrap();
+        assert_eq!(result, Value::Int(3));
+    }
+
+    #[test]
+    fn test_assert_failed() {
+        let result = run("var a = 1;\n  assert(a == 2); 3").unwrap_err();
+        assert_eq!(result.loc, CodeLocation::new(2, 3));
+        assert_eq!(format!("{result}"), "RuntimeError: assertion failed at 2:3");
+    }
+
+    #[test]
+    fn test_exit() {
+        let result = run("{ defer print_int(1); exit(3) }; 2").unwrap_err();
+        assert_eq!(result.exit_code, Some(3));
+
+        let result = run("assert(false)").unwrap_err();
+        assert_eq!(result.exit_code, None);
+    }
+
+    #[test]
+    fn test_defer_reverse_order() {
+        let result =
+            run("var log = 0; { defer log = log * 10 + 1; defer log = log * 10 + 2; 3 }; log");
+        assert_eq!(result.unwrap(), Value::Int(21));
+
+        let result = run("var log = 0; { defer log = log * 10 + 1; defer log = log * 10 + 2; 3 }");
+        assert_eq!(result.unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn test_defer_keeps_block_result() {
+        let result = run("{ var x = 1; defer x = 5; x }").unwrap();
+        assert_eq!(result, Value::Int(1));
+    }
+
+    #[test]
+    fn test_program_args() {
+        set_program_args(vec![String::from("12"), String::from("-3")]);
+
+        let result = run("arg_count()").unwrap();
+        assert_eq!(result, Value::Int(2));
+
+        let result = run("arg_int(0) + arg_int(1)").unwrap();
+        assert_eq!(result, Value::Int(9));
+    }
+
+    #[test]
+    fn test_program_args_invalid() {
+        set_program_args(vec![String::from("12a")]);
+
+        let result = run("arg_int(0)").unwrap_err();
+        assert_eq!(result.loc, CodeLocation::new(1, 1));
+
+        let result = run("1 + arg_int(1)").unwrap_err();
+        assert_eq!(result.loc, CodeLocation::new(1, 5));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let result = run("var a = 0;\n1 + 2 / a").unwrap_err();
+        assert_eq!(result.loc, CodeLocation::new(2, 7));
+        assert_eq!(result.to_string(), "RuntimeError: Division by zero! at 2:7");
+
+        let result = run("5 % (1 - 1)").unwrap_err();
+        assert_eq!(result.msg, "Division by zero!");
+        assert_eq!(run("7 % 3").unwrap(), Value::Int(1));
+    }
+
+    #[test]
+    fn test_runtime_type_errors() {
+        let result = run("1 + true").unwrap_err();
+        assert_eq!(result.msg, "Can't apply + to non-ints!");
+
+        let result = run("if 1 then 2").unwrap_err();
+        assert_eq!(result.msg, "Non-bool as if-then-else condition!");
+
+        let result = run("true and 1").unwrap_err();
+        assert_eq!(result.msg, "Non-bool with and operator!");
+
+        let result = run("print_int(1, 2)").unwrap_err();
+        assert_eq!(result.msg, "print takes 1 argument(s) but 2 were given!");
+
+        let result = run("var f = 1; f(2)").unwrap_err();
+        assert_eq!(result.msg, "f is not a function!");
+    }
+
+    #[test]
+    fn test_undefined_name() {
+        let result = run("var a = 1;\n{ b = a }").unwrap_err();
+        assert_eq!(result.loc, CodeLocation::new(2, 3));
+        assert_eq!(result.msg, "No symbol b found!");
+    }
+
+    #[test]
+    fn test_overflow() {
+        let result = run("var a = 1; while true do a = a * 2").unwrap_err();
+        assert_eq!(result.msg, "Integer overflow!");
+    }
+
+    #[test]
+    fn test_trace() {
+        let result = run("print_int(1 + arg_int(1 / 0))").unwrap_err();
+        assert_eq!(
+            result.to_string(),
+            "RuntimeError: Division by zero! at 1:25\n    \
+             in call to arg_int at 1:15\n    \
+             in call to print_int at 1:1"
+        );
+
+        let result = run("{\n  defer assert(false);\n  1\n}").unwrap_err();
+        assert_eq!(
+            result.to_string(),
+            "RuntimeError: assertion failed at 2:9\n    in expression deferred at 2:3"
+        );
     }
 }
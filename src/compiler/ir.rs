@@ -1,6 +1,9 @@
 use std::{collections::HashMap, fmt};
 
-use crate::compiler::{token::CodeLocation, variable::Type};
+use crate::compiler::{
+    token::{CodeLocation, Span},
+    variable::Type,
+};
 
 #[derive(PartialEq, Clone, Eq, Hash, Ord, PartialOrd)]
 pub struct IrVar {
@@ -32,6 +35,10 @@ impl IrVar {
             (IrVar::new("print_bool"), Func(vec![Bool], Box::new(Unit))),
             (IrVar::new("print_int"), Func(vec![Int], Box::new(Unit))),
             (IrVar::new("read_int"), Func(vec![], Box::new(Int))),
+            (IrVar::new("assert"), Func(vec![Bool], Box::new(Unit))),
+            (IrVar::new("exit"), Func(vec![Int], Box::new(Unit))),
+            (IrVar::new("arg_count"), Func(vec![], Box::new(Int))),
+            (IrVar::new("arg_int"), Func(vec![Int], Box::new(Int))),
             (IrVar::new("+"), Func(vec![Int, Int], Box::new(Int))),
             (IrVar::new("*"), Func(vec![Int, Int], Box::new(Int))),
             (IrVar::new("-"), Func(vec![Int, Int], Box::new(Int))),
@@ -41,8 +48,16 @@ impl IrVar {
             (IrVar::new("<="), Func(vec![Int, Int], Box::new(Bool))),
             (IrVar::new(">"), Func(vec![Int, Int], Box::new(Bool))),
             (IrVar::new(">="), Func(vec![Int, Int], Box::new(Bool))),
-            (IrVar::new("=="), Func(vec![Int, Int], Box::new(Bool))), // Arg types faked
-            (IrVar::new("!="), Func(vec![Int, Int], Box::new(Bool))), // Arg types faked
+            (IrVar::new("==_int"), Func(vec![Int, Int], Box::new(Bool))),
+            (
+                IrVar::new("==_bool"),
+                Func(vec![Bool, Bool], Box::new(Bool)),
+            ),
+            (IrVar::new("!=_int"), Func(vec![Int, Int], Box::new(Bool))),
+            (
+                IrVar::new("!=_bool"),
+                Func(vec![Bool, Bool], Box::new(Bool)),
+            ),
             (IrVar::new("unary_not"), Func(vec![Bool], Box::new(Bool))),
             (IrVar::new("unary_-"), Func(vec![Int], Box::new(Int))),
             (IrVar::new("or"), Func(vec![Bool, Bool], Box::new(Bool))),
@@ -60,12 +75,17 @@ impl fmt::Display for IrInstruction {
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
 pub struct IrInstruction {
     pub loc: CodeLocation,
+    pub span: Span,
     pub instruction: IrInstructionType,
 }
 
 impl IrInstruction {
-    pub fn new(loc: CodeLocation, instruction: IrInstructionType) -> Self {
-        Self { loc, instruction }
+    pub fn new(loc: CodeLocation, span: Span, instruction: IrInstructionType) -> Self {
+        Self {
+            loc,
+            span,
+            instruction,
+        }
     }
 
     pub fn get_vars(&self) -> Vec<IrVar> {
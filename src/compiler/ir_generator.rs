@@ -1,18 +1,59 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+};
 
 use crate::compiler::{
     ast::{AstNode, Expression::*},
+    diagnostic::Diagnostic,
     ir::{
         IrInstruction,
         IrInstructionType::{self, *},
         IrVar,
     },
     symtab::SymTab,
-    token::CodeLocation,
+    token::{CodeLocation, Span},
     variable::Type,
 };
 
-pub fn generate_ir(ast: &AstNode) -> Vec<IrInstruction> {
+// The type checker rejects programs these could be raised for, so they point at a bug in an
+// earlier pass rather than in the compiled program
+#[derive(Debug)]
+pub struct IrGeneratorError {
+    msg: String,
+    loc: CodeLocation,
+    span: Span,
+}
+
+impl IrGeneratorError {
+    fn at(msg: String, ast: &AstNode) -> Self {
+        Self {
+            msg,
+            loc: ast.loc,
+            span: ast.span,
+        }
+    }
+}
+
+impl Display for IrGeneratorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IrGeneratorError: {}", self.msg)
+    }
+}
+
+impl Error for IrGeneratorError {}
+
+impl From<IrGeneratorError> for Diagnostic {
+    fn from(error: IrGeneratorError) -> Self {
+        Diagnostic::error(error.msg, error.loc, error.span)
+    }
+}
+
+pub fn generate_ir(
+    ast: &AstNode,
+    exit_with_result: bool,
+) -> Result<Vec<IrInstruction>, IrGeneratorError> {
     let mut instructions = Vec::new();
 
     let mut symbols = SymTab::new();
@@ -20,7 +61,9 @@ pub fn generate_ir(ast: &AstNode) -> Vec<IrInstruction> {
     let global_types = IrVar::new_global_types();
     let mut types = global_types.clone();
     for var in global_types.keys() {
-        symbols.insert(&var.name, var.clone()).unwrap();
+        symbols
+            .insert(&var.name, var.clone())
+            .expect("Global IR variables should be unique!");
     }
 
     let result = visit_ast_node(
@@ -29,33 +72,42 @@ pub fn generate_ir(ast: &AstNode) -> Vec<IrInstruction> {
         &mut symbols,
         &mut instructions,
         &mut labels,
-    );
-
-    match types.get(&result) {
-        Some(Type::Int) => {
-            let loc = instructions.last().unwrap().loc;
-            let fn_var = symbols.get("print_int").unwrap().clone();
-            let result_var = add_var(&Type::Bool, &mut types);
-
-            instructions.push(IrInstruction::new(
-                loc,
-                Call(fn_var, vec![result], result_var),
-            ));
-        }
-        Some(Type::Bool) => {
-            let loc = instructions.last().unwrap().loc;
-            let fn_var = symbols.get("print_bool").unwrap().clone();
-            let result_var = add_var(&Type::Bool, &mut types);
-
-            instructions.push(IrInstruction::new(
-                loc,
-                Call(fn_var, vec![result], result_var),
-            ));
-        }
-        _ => (),
+    )?;
+
+    // The result is printed, or used as the exit code, at the location of the last instruction
+    let (loc, span) = instructions
+        .last()
+        .map_or((ast.loc, ast.span), |last| (last.loc, last.span));
+    let fn_name = match types.get(&result) {
+        Some(Type::Int) if exit_with_result => Some("exit"),
+        Some(Type::Int) => Some("print_int"),
+        Some(Type::Bool) => Some("print_bool"),
+        _ => None,
+    };
+
+    if let Some(fn_name) = fn_name {
+        let fn_var = get_var(fn_name, &mut symbols, ast)?;
+        let result_var = add_var(&Type::Bool, &mut types);
+
+        instructions.push(IrInstruction::new(
+            loc,
+            span,
+            Call(fn_var, vec![result], result_var),
+        ));
     }
 
-    instructions
+    Ok(instructions)
+}
+
+fn get_var(
+    name: &str,
+    symbols: &mut SymTab<'_, IrVar>,
+    ast: &AstNode,
+) -> Result<IrVar, IrGeneratorError> {
+    symbols
+        .try_get(name)
+        .cloned()
+        .ok_or_else(|| IrGeneratorError::at(format!("No IR variable for {name} found!"), ast))
 }
 
 fn add_var(var_type: &Type, types: &mut HashMap<IrVar, Type>) -> IrVar {
@@ -66,14 +118,8 @@ fn add_var(var_type: &Type, types: &mut HashMap<IrVar, Type>) -> IrVar {
             var
         }
         _ => {
-            let mut i = 1;
-            let mut var = IrVar::new(&format!("x{}", i));
-
-            while types.contains_key(&var) {
-                i += 1;
-                var = IrVar::new(&format!("x{}", i));
-            }
-
+            let i = first_free(|i| types.contains_key(&IrVar::new(&format!("x{}", i))));
+            let var = IrVar::new(&format!("x{}", i));
             types.insert(var.clone(), var_type.clone());
             var
         }
@@ -83,19 +129,34 @@ fn add_var(var_type: &Type, types: &mut HashMap<IrVar, Type>) -> IrVar {
 fn add_label(
     label: &str,
     loc: CodeLocation,
+    span: Span,
     labels: &mut HashSet<IrInstructionType>,
 ) -> IrInstruction {
-    let mut i = 1;
+    let i = first_free(|i| labels.contains(&IrInstructionType::Label(format!("{}{}", label, i))));
+    let instruction = IrInstructionType::Label(format!("{}{}", label, i));
 
-    let mut instruction = IrInstructionType::Label(format!("{}{}", label, i));
+    labels.insert(instruction.clone());
+    IrInstruction::new(loc, span, instruction)
+}
 
-    while labels.contains(&instruction) {
-        i += 1;
-        instruction = IrInstructionType::Label(format!("{}{}", label, i));
+// Names are numbered from 1 and never freed, so the numbers taken are always 1 up to some n.
+// Bisecting for n + 1 keeps long programs from trying every number for every new name.
+fn first_free(taken: impl Fn(usize) -> bool) -> usize {
+    let (mut last_taken, mut free) = (0, 1);
+    while taken(free) {
+        last_taken = free;
+        free *= 2;
     }
 
-    labels.insert(instruction.clone());
-    IrInstruction::new(loc, instruction)
+    while free - last_taken > 1 {
+        let middle = (last_taken + free) / 2;
+        if taken(middle) {
+            last_taken = middle;
+        } else {
+            free = middle;
+        }
+    }
+    free
 }
 
 fn visit_ast_node<'source>(
@@ -104,31 +165,40 @@ fn visit_ast_node<'source>(
     symbols: &mut SymTab<'source, IrVar>,
     instructions: &mut Vec<IrInstruction>,
     labels: &mut HashSet<IrInstructionType>,
-) -> IrVar {
-    match &ast.expr {
+) -> Result<IrVar, IrGeneratorError> {
+    Ok(match &ast.expr {
         EmptyLiteral() => add_var(&Type::Unit, types),
         IntLiteral(val) => {
             let var = add_var(&Type::Int, types);
+            // Range is checked by the type checker. The only exception is the absolute value of
+            // the smallest Int under a negation, which wraps to the correct result.
             let val = *val as i64;
-            instructions.push(IrInstruction::new(ast.loc, LoadIntConst(val, var.clone())));
+            instructions.push(IrInstruction::new(
+                ast.loc,
+                ast.span,
+                LoadIntConst(val, var.clone()),
+            ));
             var
         }
         BoolLiteral(val) => {
             let var = add_var(&Type::Bool, types);
-            instructions.push(IrInstruction {
-                loc: ast.loc,
-                instruction: LoadBoolConst(*val, var.clone()),
-            });
+            instructions.push(IrInstruction::new(
+                ast.loc,
+                ast.span,
+                LoadBoolConst(*val, var.clone()),
+            ));
             var
         }
-        Identifier(name) => symbols.get(name).unwrap().clone(),
+        Identifier(name) => get_var(name, symbols, ast)?,
         UnaryOp(op, expr) => {
-            let op_var = symbols.get(&format!("unary_{op}")).unwrap().clone();
-            let expr_var = visit_ast_node(expr, types, symbols, instructions, labels);
+            let op_name = format!("unary_{op}");
+            let op_var = get_var(ast.overload.unwrap_or(&op_name), symbols, ast)?;
+            let expr_var = visit_ast_node(expr, types, symbols, instructions, labels)?;
             let result_var = add_var(&ast.node_type, types);
 
             instructions.push(IrInstruction::new(
                 ast.loc,
+                ast.span,
                 Call(op_var, vec![expr_var], result_var.clone()),
             ));
 
@@ -136,24 +206,32 @@ fn visit_ast_node<'source>(
         }
         BinaryOp(left, op, right) => match *op {
             "=" => {
-                let right_var = visit_ast_node(right, types, symbols, instructions, labels);
+                let right_var = visit_ast_node(right, types, symbols, instructions, labels)?;
                 let Identifier(var_name) = left.expr else {
-                    panic!("Tried to assign to non-variable!");
+                    return Err(IrGeneratorError::at(
+                        String::from("Tried to assign to non-variable!"),
+                        left,
+                    ));
                 };
-                let var = symbols.get(var_name).unwrap().clone();
+                let var = get_var(var_name, symbols, left)?;
 
-                instructions.push(IrInstruction::new(right.loc, Copy(right_var, var.clone())));
+                instructions.push(IrInstruction::new(
+                    right.loc,
+                    right.span,
+                    Copy(right_var, var.clone()),
+                ));
 
                 var
             }
             "and" => {
-                let l_right = add_label("and_right", right.loc, labels);
-                let l_skip = add_label("and_skip", ast.loc, labels);
-                let l_end = add_label("and_end", ast.loc, labels);
+                let l_right = add_label("and_right", right.loc, right.span, labels);
+                let l_skip = add_label("and_skip", ast.loc, ast.span, labels);
+                let l_end = add_label("and_end", ast.loc, ast.span, labels);
 
-                let left_var = visit_ast_node(left, types, symbols, instructions, labels);
+                let left_var = visit_ast_node(left, types, symbols, instructions, labels)?;
                 instructions.push(IrInstruction::new(
                     left.loc,
+                    left.span,
                     CondJump(
                         left_var,
                         Box::new(l_right.clone()),
@@ -162,32 +240,43 @@ fn visit_ast_node<'source>(
                 ));
 
                 instructions.push(l_right);
-                let right_var = visit_ast_node(right, types, symbols, instructions, labels);
+                let right_var = visit_ast_node(right, types, symbols, instructions, labels)?;
                 let result_var = add_var(&ast.node_type, types);
                 instructions.push(IrInstruction::new(
                     right.loc,
+                    right.span,
                     Copy(right_var, result_var.clone()),
                 ));
-                instructions.push(IrInstruction::new(right.loc, Jump(Box::new(l_end.clone()))));
+                instructions.push(IrInstruction::new(
+                    right.loc,
+                    right.span,
+                    Jump(Box::new(l_end.clone())),
+                ));
 
                 instructions.push(l_skip);
                 instructions.push(IrInstruction::new(
                     right.loc,
+                    right.span,
                     LoadBoolConst(false, result_var.clone()),
                 ));
-                instructions.push(IrInstruction::new(right.loc, Jump(Box::new(l_end.clone()))));
+                instructions.push(IrInstruction::new(
+                    right.loc,
+                    right.span,
+                    Jump(Box::new(l_end.clone())),
+                ));
 
                 instructions.push(l_end);
                 result_var
             }
             "or" => {
-                let l_right = add_label("or_right", right.loc, labels);
-                let l_skip = add_label("or_skip", ast.loc, labels);
-                let l_end = add_label("or_end", ast.loc, labels);
+                let l_right = add_label("or_right", right.loc, right.span, labels);
+                let l_skip = add_label("or_skip", ast.loc, ast.span, labels);
+                let l_end = add_label("or_end", ast.loc, ast.span, labels);
 
-                let left_var = visit_ast_node(left, types, symbols, instructions, labels);
+                let left_var = visit_ast_node(left, types, symbols, instructions, labels)?;
                 instructions.push(IrInstruction::new(
                     left.loc,
+                    left.span,
                     CondJump(
                         left_var,
                         Box::new(l_skip.clone()),
@@ -196,32 +285,43 @@ fn visit_ast_node<'source>(
                 ));
 
                 instructions.push(l_right);
-                let right_var = visit_ast_node(right, types, symbols, instructions, labels);
+                let right_var = visit_ast_node(right, types, symbols, instructions, labels)?;
                 let result_var = add_var(&ast.node_type, types);
                 instructions.push(IrInstruction::new(
                     right.loc,
+                    right.span,
                     Copy(right_var, result_var.clone()),
                 ));
-                instructions.push(IrInstruction::new(right.loc, Jump(Box::new(l_end.clone()))));
+                instructions.push(IrInstruction::new(
+                    right.loc,
+                    right.span,
+                    Jump(Box::new(l_end.clone())),
+                ));
 
                 instructions.push(l_skip);
                 instructions.push(IrInstruction::new(
                     right.loc,
+                    right.span,
                     LoadBoolConst(true, result_var.clone()),
                 ));
-                instructions.push(IrInstruction::new(right.loc, Jump(Box::new(l_end.clone()))));
+                instructions.push(IrInstruction::new(
+                    right.loc,
+                    right.span,
+                    Jump(Box::new(l_end.clone())),
+                ));
 
                 instructions.push(l_end);
                 result_var
             }
             _ => {
-                let op_var = symbols.get(op).unwrap().clone();
-                let left_var = visit_ast_node(left, types, symbols, instructions, labels);
-                let right_var = visit_ast_node(right, types, symbols, instructions, labels);
+                let op_var = get_var(ast.overload.unwrap_or(op), symbols, ast)?;
+                let left_var = visit_ast_node(left, types, symbols, instructions, labels)?;
+                let right_var = visit_ast_node(right, types, symbols, instructions, labels)?;
                 let result_var = add_var(&ast.node_type, types);
 
                 instructions.push(IrInstruction::new(
                     ast.loc,
+                    ast.span,
                     Call(op_var, vec![left_var, right_var], result_var.clone()),
                 ));
 
@@ -229,41 +329,52 @@ fn visit_ast_node<'source>(
             }
         },
         VarDeclaration(name, expr, _) => {
-            let expr_var = visit_ast_node(expr, types, symbols, instructions, labels);
+            let expr_var = visit_ast_node(expr, types, symbols, instructions, labels)?;
             let result_var = add_var(&expr.node_type, types);
-            symbols.insert(name, result_var.clone()).unwrap();
-            instructions.push(IrInstruction::new(expr.loc, Copy(expr_var, result_var)));
+            symbols.insert(name, result_var.clone()).map_err(|_| {
+                IrGeneratorError::at(format!("Variable {name} already defined!"), ast)
+            })?;
+            instructions.push(IrInstruction::new(
+                expr.loc,
+                expr.span,
+                Copy(expr_var, result_var),
+            ));
             add_var(&Type::Unit, types)
         }
         Conditional(condition_expr, then_expr, else_expr) => match else_expr {
             Some(else_expr) => {
-                let l_then = add_label("then", then_expr.loc, labels);
-                let l_else = add_label("else", else_expr.loc, labels);
-                let l_end = add_label("if_end", else_expr.loc, labels);
+                let l_then = add_label("then", then_expr.loc, then_expr.span, labels);
+                let l_else = add_label("else", else_expr.loc, else_expr.span, labels);
+                let l_end = add_label("if_end", else_expr.loc, else_expr.span, labels);
 
-                let cond_var = visit_ast_node(condition_expr, types, symbols, instructions, labels);
+                let cond_var =
+                    visit_ast_node(condition_expr, types, symbols, instructions, labels)?;
                 let result_var = add_var(&ast.node_type, types);
 
                 instructions.push(IrInstruction::new(
                     condition_expr.loc,
+                    condition_expr.span,
                     CondJump(cond_var, Box::new(l_then.clone()), Box::new(l_else.clone())),
                 ));
 
                 instructions.push(l_then);
-                let then_var = visit_ast_node(then_expr, types, symbols, instructions, labels);
+                let then_var = visit_ast_node(then_expr, types, symbols, instructions, labels)?;
                 instructions.push(IrInstruction::new(
                     else_expr.loc,
+                    else_expr.span,
                     Copy(then_var, result_var.clone()),
                 ));
                 instructions.push(IrInstruction::new(
                     else_expr.loc,
+                    else_expr.span,
                     Jump(Box::new(l_end.clone())),
                 ));
 
                 instructions.push(l_else);
-                let else_var = visit_ast_node(else_expr, types, symbols, instructions, labels);
+                let else_var = visit_ast_node(else_expr, types, symbols, instructions, labels)?;
                 instructions.push(IrInstruction::new(
                     else_expr.loc,
+                    else_expr.span,
                     Copy(else_var, result_var.clone()),
                 ));
                 instructions.push(l_end);
@@ -271,45 +382,60 @@ fn visit_ast_node<'source>(
                 result_var
             }
             None => {
-                let l_then = add_label("then", then_expr.loc, labels);
-                let l_end = add_label("if_end", then_expr.loc, labels);
+                let l_then = add_label("then", then_expr.loc, then_expr.span, labels);
+                let l_end = add_label("if_end", then_expr.loc, then_expr.span, labels);
 
-                let cond_var = visit_ast_node(condition_expr, types, symbols, instructions, labels);
+                let cond_var =
+                    visit_ast_node(condition_expr, types, symbols, instructions, labels)?;
 
                 instructions.push(IrInstruction::new(
                     condition_expr.loc,
+                    condition_expr.span,
                     CondJump(cond_var, Box::new(l_then.clone()), Box::new(l_end.clone())),
                 ));
 
                 instructions.push(l_then);
-                visit_ast_node(then_expr, types, symbols, instructions, labels);
+                visit_ast_node(then_expr, types, symbols, instructions, labels)?;
                 instructions.push(l_end);
 
                 add_var(&Type::Unit, types)
             }
         },
         While(condition_expr, do_expr) => {
-            let l_start = add_label("while_start", condition_expr.loc, labels);
-            let l_body = add_label("while_body", do_expr.loc, labels);
-            let l_end = add_label("while_end", do_expr.loc, labels);
+            let l_start = add_label(
+                "while_start",
+                condition_expr.loc,
+                condition_expr.span,
+                labels,
+            );
+            let l_body = add_label("while_body", do_expr.loc, do_expr.span, labels);
+            let l_end = add_label("while_end", do_expr.loc, do_expr.span, labels);
 
             instructions.push(l_start.clone());
-            let cond_var = visit_ast_node(condition_expr, types, symbols, instructions, labels);
+            let cond_var = visit_ast_node(condition_expr, types, symbols, instructions, labels)?;
             instructions.push(IrInstruction::new(
                 condition_expr.loc,
+                condition_expr.span,
                 CondJump(cond_var, Box::new(l_body.clone()), Box::new(l_end.clone())),
             ));
             instructions.push(l_body);
-            visit_ast_node(do_expr, types, symbols, instructions, labels);
-            instructions.push(IrInstruction::new(do_expr.loc, Jump(Box::new(l_start))));
+            visit_ast_node(do_expr, types, symbols, instructions, labels)?;
+            instructions.push(IrInstruction::new(
+                do_expr.loc,
+                do_expr.span,
+                Jump(Box::new(l_start)),
+            ));
             instructions.push(l_end);
 
             add_var(&Type::Unit, types)
         }
         FunCall(name, expressions) => {
-            let fn_var = symbols.get(name).unwrap().clone();
-            let Type::Func(_, result_type) = types.get(&fn_var).unwrap().clone() else {
-                panic!("Function call does not have entry in types table!");
+            let fn_var = get_var(ast.overload.unwrap_or(name), symbols, ast)?;
+            let Some(Type::Func(_, result_type)) = types.get(&fn_var).cloned() else {
+                return Err(IrGeneratorError::at(
+                    format!("Called {name} does not have a function type!"),
+                    ast,
+                ));
             };
 
             let mut args = Vec::new();
@@ -320,12 +446,13 @@ fn visit_ast_node<'source>(
                     symbols,
                     instructions,
                     labels,
-                ));
+                )?);
             }
             let result_var = add_var(&result_type, types);
 
             instructions.push(IrInstruction::new(
                 ast.loc,
+                ast.span,
                 Call(fn_var, args, result_var.clone()),
             ));
 
@@ -333,12 +460,108 @@ fn visit_ast_node<'source>(
         }
         Block(expressions) => {
             let mut result_var = add_var(&Type::Unit, types);
+            let mut deferred = Vec::new();
             symbols.push_level();
             for expression in expressions {
-                result_var = visit_ast_node(expression, types, symbols, instructions, labels);
+                if let Defer(deferred_expr) = &expression.expr {
+                    deferred.push(deferred_expr.as_ref());
+                    result_var = add_var(&Type::Unit, types);
+                } else {
+                    result_var = visit_ast_node(expression, types, symbols, instructions, labels)?;
+                }
+            }
+
+            if !deferred.is_empty() {
+                // The deferred expressions may modify the variable holding the result
+                if types.get(&result_var) != Some(&Type::Unit) {
+                    let saved_var = add_var(&ast.node_type, types);
+                    instructions.push(IrInstruction::new(
+                        ast.loc,
+                        ast.span,
+                        Copy(result_var, saved_var.clone()),
+                    ));
+                    result_var = saved_var;
+                }
+                emit_deferred(&deferred, types, symbols, instructions, labels)?;
             }
+
             symbols.remove_level();
             result_var
         }
+        // Deferred expressions are collected and emitted by the enclosing block
+        Defer(_) => add_var(&Type::Unit, types),
+        // Macro definitions are removed by macro expansion
+        MacroDefinition(..) => add_var(&Type::Unit, types),
+        Error(msg) => {
+            return Err(IrGeneratorError::at(
+                format!("Syntax error in compiled code: {}", msg),
+                ast,
+            ))
+        }
+    })
+}
+
+// Must be called on every path that exits a block, before its symbol level is removed
+fn emit_deferred<'source>(
+    deferred: &[&AstNode<'source>],
+    types: &mut HashMap<IrVar, Type>,
+    symbols: &mut SymTab<'source, IrVar>,
+    instructions: &mut Vec<IrInstruction>,
+    labels: &mut HashSet<IrInstructionType>,
+) -> Result<(), IrGeneratorError> {
+    for expression in deferred.iter().rev() {
+        visit_ast_node(expression, types, symbols, instructions, labels)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{parser::parse, tokenizer::tokenize, type_checker::type_check};
+
+    fn ir(code: &str) -> Vec<String> {
+        let tokens = tokenize(code).unwrap();
+        let mut ast = parse(&tokens).unwrap();
+        type_check(&mut ast, &mut SymTab::new_type_table()).unwrap();
+        generate_ir(&ast, false)
+            .unwrap()
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_defer_reverse_order() {
+        let result = ir("{ defer print_int(1); defer print_int(2); 3 }");
+        assert_eq!(
+            result,
+            vec![
+                "LoadIntConst(3, x1)",
+                "Copy(x1, x2)",
+                "LoadIntConst(2, x3)",
+                "Call(print_int, [x3], unit)",
+                "LoadIntConst(1, x4)",
+                "Call(print_int, [x4], unit)",
+                "Call(print_int, [x2], x5)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_defer_keeps_block_result() {
+        // x2 is the variable, its value is saved in x3 before the deferred assignment
+        let result = ir("{ var x = 1; defer x = 5; x }");
+        assert_eq!(
+            result,
+            vec![
+                "LoadIntConst(1, x1)",
+                "Copy(x1, x2)",
+                "Copy(x2, x3)",
+                "LoadIntConst(5, x4)",
+                "Copy(x4, x2)",
+                "Call(print_int, [x3], x5)",
+            ]
+        );
     }
 }
@@ -0,0 +1,74 @@
+use crate::compiler::{
+    ast::{AstNode, Expression::*},
+    diagnostic::Diagnostic,
+    lints::{Level, Lint},
+};
+
+const DEFAULT_MAX_DEPTH: usize = 4;
+
+// Limits how deeply blocks can be nested inside each other
+pub struct BlockNesting {
+    max_depth: usize,
+}
+
+impl Default for BlockNesting {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl Lint for BlockNesting {
+    fn name(&self) -> &'static str {
+        "block-nesting"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Allow
+    }
+
+    fn configure(&mut self, option: &str, value: &str) -> Result<(), String> {
+        if option != "max-depth" {
+            return Err(format!("Lint {} has no option {}", self.name(), option));
+        }
+
+        self.max_depth = value.parse().map_err(|_| {
+            format!(
+                "Invalid max-depth {}, expected a non-negative integer",
+                value
+            )
+        })?;
+        Ok(())
+    }
+
+    // Only the first block past the limit is reported, not every block nested inside it
+    fn check(&self, ast: &AstNode, ancestors: &[&AstNode], lints: &mut Vec<Diagnostic>) {
+        if !braced(ast) {
+            return;
+        }
+
+        let depth = ancestors.iter().filter(|ancestor| braced(ancestor)).count() + 1;
+        if depth == self.max_depth + 1 {
+            lints.push(Diagnostic::warning(
+                format!(
+                    "Block nested {} deep, the limit is {}",
+                    depth, self.max_depth
+                ),
+                ast.loc,
+                ast.span,
+            ));
+        }
+    }
+}
+
+// Blocks written in braces. A program with several top level expressions is also parsed into a
+// block, but one starting at its first expression rather than at a brace before it.
+fn braced(ast: &AstNode) -> bool {
+    match &ast.expr {
+        Block(expressions) => expressions
+            .first()
+            .is_none_or(|first| first.span.start > ast.span.start),
+        _ => false,
+    }
+}
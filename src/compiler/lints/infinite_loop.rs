@@ -0,0 +1,42 @@
+use crate::compiler::{
+    ast::{AstNode, Expression::*},
+    diagnostic::Diagnostic,
+    lints::{Level, Lint},
+};
+
+// There is no way to break out of a loop, so a `while true` loop only ends by exiting the program
+pub struct InfiniteLoop;
+
+impl Lint for InfiniteLoop {
+    fn name(&self) -> &'static str {
+        "infinite-loop"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn check(&self, ast: &AstNode, _ancestors: &[&AstNode], lints: &mut Vec<Diagnostic>) {
+        let While(condition_expr, do_expr) = &ast.expr else {
+            return;
+        };
+
+        if matches!(condition_expr.expr, BoolLiteral(true)) && !calls_exit(do_expr) {
+            let diagnostic = Diagnostic::warning(
+                String::from("Loop never ends, there is no call to exit in its body"),
+                ast.loc,
+                ast.span,
+            )
+            .with_label(
+                condition_expr.loc,
+                condition_expr.span,
+                String::from("Condition is always true"),
+            );
+            lints.push(diagnostic);
+        }
+    }
+}
+
+fn calls_exit(ast: &AstNode) -> bool {
+    matches!(ast.expr, FunCall("exit", _)) || ast.children().into_iter().any(calls_exit)
+}
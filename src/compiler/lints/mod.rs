@@ -0,0 +1,266 @@
+mod block_nesting;
+mod infinite_loop;
+#[cfg(test)]
+mod tests;
+mod variable_naming;
+
+use crate::compiler::{
+    ast::AstNode,
+    diagnostic::{Diagnostic, Severity},
+    lints::{
+        block_nesting::BlockNesting, infinite_loop::InfiniteLoop, variable_naming::VariableNaming,
+    },
+    suggestions::{closest_names, did_you_mean},
+    tokenizer::tokenize_comments,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    // Not checked at all
+    Allow,
+    Warn,
+    // Reported as an error, failing the compilation
+    Deny,
+}
+
+impl Level {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            _ => Err(format!(
+                "Unknown lint level {}, expected allow, warn or deny",
+                name
+            )),
+        }
+    }
+}
+
+// A house rule checked on the type checked AST. The lint is given every node along with the
+// nodes enclosing it, outermost first, and reports what it finds as warnings. Whether they end up
+// as warnings or errors depends on the level the lint is set to.
+pub trait Lint {
+    // Used in the configuration file and in allow comments
+    fn name(&self) -> &'static str;
+
+    fn default_level(&self) -> Level;
+
+    // Sets an option from the configuration file, e.g. `block-nesting.max-depth = 3`
+    fn configure(&mut self, option: &str, _value: &str) -> Result<(), String> {
+        Err(format!("Lint {} has no option {}", self.name(), option))
+    }
+
+    fn check(&self, ast: &AstNode, ancestors: &[&AstNode], lints: &mut Vec<Diagnostic>);
+}
+
+#[derive(Debug, Clone)]
+enum Setting {
+    Level(String, Level),
+    Option(String, String, String),
+}
+
+// Levels and options for the lints, applied in order on top of the defaults
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    settings: Vec<Setting>,
+}
+
+impl LintConfig {
+    // One setting per line, with `#` starting a comment:
+    //
+    // infinite-loop = deny
+    // block-nesting = warn
+    // block-nesting.max-depth = 3
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        // Every setting is tried out as it's read, so errors point at the line that caused them
+        let mut registry = LintRegistry::builtin();
+
+        for (i, line) in text.lines().enumerate() {
+            let located = |msg: String| format!("Lint configuration line {}: {}", i + 1, msg);
+
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(located(format!(
+                    "Expected a setting like name = level but found {}",
+                    line
+                )));
+            };
+
+            let (key, value) = (key.trim(), value.trim());
+            let setting = match key.split_once('.') {
+                Some((name, option)) => {
+                    Setting::Option(name.to_string(), option.to_string(), value.to_string())
+                }
+                None => Setting::Level(key.to_string(), Level::from_name(value).map_err(located)?),
+            };
+            registry.apply(&setting).map_err(located)?;
+            config.settings.push(setting);
+        }
+
+        Ok(config)
+    }
+}
+
+pub struct LintRegistry {
+    lints: Vec<(Box<dyn Lint>, Level)>,
+}
+
+impl LintRegistry {
+    // Every lint that comes with the compiler, at its default level
+    pub fn builtin() -> Self {
+        let mut registry = Self { lints: Vec::new() };
+        registry.register(Box::new(InfiniteLoop));
+        registry.register(Box::new(BlockNesting::default()));
+        registry.register(Box::new(VariableNaming));
+        registry
+    }
+
+    pub fn register(&mut self, lint: Box<dyn Lint>) {
+        let level = lint.default_level();
+        self.lints.push((lint, level));
+    }
+
+    pub fn configure(&mut self, config: &LintConfig) -> Result<(), String> {
+        config
+            .settings
+            .iter()
+            .try_for_each(|setting| self.apply(setting))
+    }
+
+    fn apply(&mut self, setting: &Setting) -> Result<(), String> {
+        match setting {
+            Setting::Level(name, level) => self.find(name)?.1 = *level,
+            Setting::Option(name, option, value) => self.find(name)?.0.configure(option, value)?,
+        }
+        Ok(())
+    }
+
+    fn find(&mut self, name: &str) -> Result<&mut (Box<dyn Lint>, Level), String> {
+        let names = self.names();
+        let suggestion = did_you_mean(&closest_names(name, names, 3));
+
+        self.lints
+            .iter_mut()
+            .find(|(lint, _)| lint.name() == name)
+            .ok_or_else(|| match suggestion {
+                Some(suggestion) => format!("Unknown lint {}. {}", name, suggestion),
+                None => format!("Unknown lint {}", name),
+            })
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        self.lints.iter().map(|(lint, _)| lint.name()).collect()
+    }
+
+    // Runs the lints that aren't allowed over the whole AST. Anything on a line with an allow
+    // comment for the lint, or on the line after it, is left out.
+    pub fn run(&self, ast: &AstNode, code: &str) -> Vec<Diagnostic> {
+        let mut found = Vec::new();
+        self.visit(ast, &mut Vec::new(), &mut found);
+
+        let (allowed, mut lints) = self.allow_comments(code);
+        lints.extend(
+            found
+                .into_iter()
+                .filter(|(name, lint)| !allowed.contains(&(lint.primary.loc.line(), name)))
+                .map(|(_, lint)| lint),
+        );
+        lints.sort_by_key(|lint| lint.primary.span.start);
+        lints
+    }
+
+    fn visit<'a, 'source>(
+        &self,
+        ast: &'a AstNode<'source>,
+        ancestors: &mut Vec<&'a AstNode<'source>>,
+        found: &mut Vec<(&'static str, Diagnostic)>,
+    ) {
+        for (lint, level) in &self.lints {
+            let severity = match level {
+                Level::Allow => continue,
+                Level::Warn => Severity::Warning,
+                Level::Deny => Severity::Error,
+            };
+
+            let mut lints = Vec::new();
+            lint.check(ast, ancestors, &mut lints);
+            for mut diagnostic in lints {
+                diagnostic.severity = severity;
+                // A block comment in front of the code works even when every line is compiled on
+                // its own, as with -c, where there is no line above to put a comment on
+                let note = format!(
+                    "Reported by the {} lint, put /* allow({}) */ in front of it to silence it",
+                    lint.name(),
+                    lint.name()
+                );
+                found.push((lint.name(), diagnostic.with_note(note)));
+            }
+        }
+
+        ancestors.push(ast);
+        for child in ast.children() {
+            self.visit(child, ancestors, found);
+        }
+        ancestors.pop();
+    }
+
+    // The lines and lints each `// allow(a, b)` comment covers, along with warnings for any
+    // names that aren't lints
+    fn allow_comments(&self, code: &str) -> (Vec<(usize, &'static str)>, Vec<Diagnostic>) {
+        let mut allowed = Vec::new();
+        let mut unknown = Vec::new();
+        // The code was already tokenized successfully before linting
+        let comments = tokenize_comments(code).unwrap_or_default();
+        let names = self.names();
+
+        for comment in comments {
+            let text = comment.text.trim_start_matches(['/', '#', '*']);
+            let text = text.strip_suffix("*/").unwrap_or(text).trim();
+            let Some(list) = text
+                .strip_prefix("allow(")
+                .and_then(|list| list.strip_suffix(')'))
+            else {
+                continue;
+            };
+
+            let first_line = comment.loc.line();
+            let last_line = first_line + comment.text.matches('\n').count() + 1;
+            for name in list.split(',').map(str::trim) {
+                match names.iter().find(|lint| **lint == name) {
+                    Some(lint) => {
+                        allowed.extend((first_line..=last_line).map(|line| (line, *lint)))
+                    }
+                    None => {
+                        let mut diagnostic = Diagnostic::warning(
+                            format!("Unknown lint {} in allow comment", name),
+                            comment.loc,
+                            comment.span,
+                        );
+                        if let Some(suggestion) =
+                            did_you_mean(&closest_names(name, names.iter().copied(), 3))
+                        {
+                            diagnostic = diagnostic.with_note(suggestion);
+                        }
+                        unknown.push(diagnostic);
+                    }
+                }
+            }
+        }
+
+        (allowed, unknown)
+    }
+}
+
+// Runs the built in lints with the given configuration, returning what they found in source order
+pub fn check_lints(ast: &AstNode, code: &str, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut registry = LintRegistry::builtin();
+    registry
+        .configure(config)
+        .expect("Lint configurations are checked when parsed!");
+    registry.run(ast, code)
+}
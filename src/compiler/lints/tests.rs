@@ -0,0 +1,167 @@
+use super::*;
+use crate::compiler::{
+    macro_expander::{expand_macros, FreshNames},
+    parser::parse,
+    symtab::SymTab,
+    token::CodeLocation,
+    tokenizer::tokenize,
+    type_checker::type_check,
+};
+
+fn lints_with(code: &str, config: &str) -> Vec<Diagnostic> {
+    let names = FreshNames::default();
+    let tokens = tokenize(code).unwrap();
+    let mut ast = parse(&tokens).unwrap();
+    expand_macros(&mut ast, &names).unwrap();
+    type_check(&mut ast, &mut SymTab::new_type_table()).unwrap();
+
+    check_lints(&ast, code, &LintConfig::parse(config).unwrap())
+}
+
+fn lints(code: &str, config: &str) -> Vec<String> {
+    lints_with(code, config)
+        .into_iter()
+        .map(|lint| lint.msg)
+        .collect()
+}
+
+#[test]
+fn test_infinite_loop() {
+    let result = lints("var a = 1; while true do a = a + 1", "");
+    assert_eq!(
+        result,
+        vec!["Loop never ends, there is no call to exit in its body"]
+    );
+
+    let result = lints("while true do if read_int() == 0 then exit(0)", "");
+    assert!(result.is_empty());
+
+    let result = lints("var a = 1; while a < 10 do a = a + 1", "");
+    assert!(result.is_empty());
+
+    let result = lints("while true do 1", "infinite-loop = allow");
+    assert!(result.is_empty());
+}
+
+#[test]
+fn test_block_nesting() {
+    let code = "{ { { { { { 1 } } } } } }";
+    assert!(lints(code, "").is_empty());
+
+    let result = lints(code, "block-nesting = warn");
+    assert_eq!(result, vec!["Block nested 5 deep, the limit is 4"]);
+
+    let result = lints(code, "block-nesting = warn\nblock-nesting.max-depth = 2");
+    assert_eq!(result, vec!["Block nested 3 deep, the limit is 2"]);
+
+    // The top level of a program isn't a block of its own
+    let result = lints(
+        "1; { 2 }; { { 3 } }",
+        "block-nesting=warn\nblock-nesting.max-depth=1",
+    );
+    assert_eq!(result, vec!["Block nested 2 deep, the limit is 1"]);
+}
+
+#[test]
+fn test_variable_naming() {
+    let config = "variable-naming = warn";
+    let result = lints_with(
+        "var myVar = 1; var HTTPServer = 2; var snake_case2 = 3; var _x = 4;",
+        config,
+    );
+    let result = result
+        .iter()
+        .map(|lint| (lint.msg.as_str(), lint.notes[0].as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        result,
+        vec![
+            (
+                "Variable myVar should have a snake_case name",
+                "Rename it to my_var"
+            ),
+            (
+                "Variable HTTPServer should have a snake_case name",
+                "Rename it to http_server"
+            ),
+        ]
+    );
+
+    let result = lints("macro m(x) { var Tmp = x; Tmp }; m(1)", config);
+    assert!(result.is_empty());
+}
+
+#[test]
+fn test_lint_levels() {
+    let result = lints_with("while true do 1", "");
+    assert_eq!(result[0].severity, Severity::Warning);
+    assert_eq!(
+        result[0].notes,
+        vec![
+            "Reported by the infinite-loop lint, put /* allow(infinite-loop) */ in front of it to silence it"
+        ]
+    );
+
+    // Following the note silences the lint even on a single line
+    assert!(lints("/* allow(infinite-loop) */ while true do 1", "").is_empty());
+
+    let result = lints_with("while true do 1", "infinite-loop = deny");
+    assert_eq!(result[0].severity, Severity::Error);
+}
+
+#[test]
+fn test_allow_comments() {
+    let config = "variable-naming = warn";
+    let code = "// allow(variable-naming)\nvar aB = 1;\nvar cD = 2;";
+    let result = lints_with(code, config);
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].primary.loc, CodeLocation::new(3, 1));
+
+    let code = "var aB = 1; # allow(infinite-loop, variable-naming)\nwhile true do 1";
+    assert!(lints(code, config).is_empty());
+
+    let code = "/* allow(infinite-loop) */ var aB = 1; while true do 1";
+    assert_eq!(
+        lints(code, config),
+        vec!["Variable aB should have a snake_case name"]
+    );
+
+    // A misspelt name doesn't allow anything
+    let result = lints_with("// allow(infinite-lop)\nwhile true do 1", "");
+    assert_eq!(result[0].msg, "Unknown lint infinite-lop in allow comment");
+    assert_eq!(result[0].notes, vec!["Did you mean infinite-loop?"]);
+    assert_eq!(result.len(), 2);
+}
+
+#[test]
+fn test_config_errors() {
+    let result = LintConfig::parse("# Comment\n\ninfinite-lop = deny").unwrap_err();
+    assert_eq!(
+        result,
+        "Lint configuration line 3: Unknown lint infinite-lop. Did you mean infinite-loop?"
+    );
+
+    let result = LintConfig::parse("infinite-loop = forbid").unwrap_err();
+    assert_eq!(
+        result,
+        "Lint configuration line 1: Unknown lint level forbid, expected allow, warn or deny"
+    );
+
+    let result = LintConfig::parse("block-nesting.max-depth = -1").unwrap_err();
+    assert_eq!(
+        result,
+        "Lint configuration line 1: Invalid max-depth -1, expected a non-negative integer"
+    );
+
+    let result = LintConfig::parse("infinite-loop.max-depth = 1").unwrap_err();
+    assert_eq!(
+        result,
+        "Lint configuration line 1: Lint infinite-loop has no option max-depth"
+    );
+
+    let result = LintConfig::parse("infinite-loop").unwrap_err();
+    assert_eq!(
+        result,
+        "Lint configuration line 1: Expected a setting like name = level but found infinite-loop"
+    );
+}
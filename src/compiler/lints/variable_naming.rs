@@ -0,0 +1,59 @@
+use crate::compiler::{
+    ast::{AstNode, Expression::*},
+    diagnostic::Diagnostic,
+    lints::{Level, Lint},
+};
+
+// Variables are named in snake_case
+pub struct VariableNaming;
+
+impl Lint for VariableNaming {
+    fn name(&self) -> &'static str {
+        "variable-naming"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Allow
+    }
+
+    // Variables declared in macros are renamed with a '$' when expanded. They are left out, as
+    // they would be reported at every call of the macro.
+    fn check(&self, ast: &AstNode, _ancestors: &[&AstNode], lints: &mut Vec<Diagnostic>) {
+        let VarDeclaration(name, _, _) = ast.expr else {
+            return;
+        };
+
+        if !name.contains('$') && name.chars().any(char::is_uppercase) {
+            let diagnostic = Diagnostic::warning(
+                format!("Variable {} should have a snake_case name", name),
+                ast.loc,
+                ast.span,
+            )
+            .with_note(format!("Rename it to {}", to_snake_case(name)));
+            lints.push(diagnostic);
+        }
+    }
+}
+
+// Words start at an uppercase letter following a lowercase one or a digit, or at the last
+// letter of a run of capitals followed by lowercase, so HTTPServer becomes http_server
+fn to_snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut snake_case = String::new();
+
+    for (i, c) in chars.iter().enumerate() {
+        if i > 0 && c.is_uppercase() {
+            let previous = chars[i - 1];
+            let next = chars.get(i + 1).copied().unwrap_or_default();
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next.is_lowercase())
+            {
+                snake_case.push('_');
+            }
+        }
+        snake_case.extend(c.to_lowercase());
+    }
+
+    snake_case
+}
@@ -0,0 +1,496 @@
+use std::{cell::Cell, collections::HashMap, error::Error, fmt::Display};
+
+use typed_arena::Arena;
+
+use crate::compiler::{
+    ast::{AstNode, Expression::*},
+    diagnostic::Diagnostic,
+    error_codes::ErrorCode,
+    parser::MAX_TREE_DEPTH,
+    symtab::SymTab,
+    token::{CodeLocation, Span},
+};
+
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub struct MacroError {
+    code: ErrorCode,
+    msg: String,
+    loc: CodeLocation,
+    span: Span,
+}
+
+impl Display for MacroError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MacroError: {}", self.msg)
+    }
+}
+
+impl Error for MacroError {}
+
+impl From<MacroError> for Diagnostic {
+    fn from(error: MacroError) -> Self {
+        Diagnostic::error(error.msg, error.loc, error.span).with_code(error.code)
+    }
+}
+
+#[derive(Clone)]
+struct Macro<'source> {
+    params: Vec<&'source str>,
+    template: AstNode<'source>,
+}
+
+// Names made up while expanding macros. The AST refers to them along with names from the source
+// code, so they are kept for as long as the source is.
+#[derive(Default)]
+pub struct FreshNames {
+    names: Arena<String>,
+    counter: Cell<usize>,
+}
+
+impl FreshNames {
+    // Generated names contain a character the tokenizer never accepts, so they can't clash with
+    // user-written names
+    fn fresh(&self, name: &str) -> &str {
+        self.counter.set(self.counter.get() + 1);
+        self.names.alloc(format!("{}${}", name, self.counter.get()))
+    }
+}
+
+// Macros are expanded in place, with the arguments substituted by name into the template.
+// Variables declared by the template are renamed, so they can't capture the arguments.
+pub fn expand_macros<'source>(
+    ast: &mut AstNode<'source>,
+    names: &'source FreshNames,
+) -> Result<(), MacroError> {
+    let mut macros = SymTab::new();
+    let mut placeholders = HashMap::new();
+    expand(ast, &mut macros, names, &mut placeholders, 0, 1)?;
+    substitute(ast, &placeholders, 1)
+}
+
+fn expand<'source>(
+    ast: &mut AstNode<'source>,
+    macros: &mut SymTab<'source, Macro<'source>>,
+    names: &'source FreshNames,
+    placeholders: &mut HashMap<&'source str, AstNode<'source>>,
+    depth: usize,
+    level: usize,
+) -> Result<(), MacroError> {
+    // Expansions take the place of the call, so this is also how deep the node ends up
+    if level > MAX_TREE_DEPTH {
+        return Err(too_deep(ast));
+    }
+
+    match &mut ast.expr {
+        EmptyLiteral() | IntLiteral(_) | BoolLiteral(_) | Identifier(_) | Error(_) => (),
+        UnaryOp(_, ref mut expr) | Defer(ref mut expr) => {
+            expand(expr, macros, names, placeholders, depth, level + 1)?
+        }
+        BinaryOp(ref mut left, _, ref mut right) => {
+            expand(left, macros, names, placeholders, depth, level + 1)?;
+            expand(right, macros, names, placeholders, depth, level + 1)?;
+        }
+        VarDeclaration(_, ref mut expr, _) => {
+            expand(expr, macros, names, placeholders, depth, level + 1)?
+        }
+        Conditional(ref mut condition_expr, ref mut then_expr, ref mut else_expr) => {
+            expand(
+                condition_expr,
+                macros,
+                names,
+                placeholders,
+                depth,
+                level + 1,
+            )?;
+            expand(then_expr, macros, names, placeholders, depth, level + 1)?;
+            if let Some(ref mut else_expr) = else_expr {
+                expand(else_expr, macros, names, placeholders, depth, level + 1)?;
+            }
+        }
+        While(ref mut condition_expr, ref mut do_expr) => {
+            expand(
+                condition_expr,
+                macros,
+                names,
+                placeholders,
+                depth,
+                level + 1,
+            )?;
+            expand(do_expr, macros, names, placeholders, depth, level + 1)?;
+        }
+        FunCall(name, ref mut args) => {
+            for arg in args.iter_mut() {
+                expand(arg, macros, names, placeholders, depth, level + 1)?;
+            }
+
+            let Some(definition) = macros.try_get(name) else {
+                return Ok(());
+            };
+            let definition = definition.clone();
+
+            if depth >= MAX_EXPANSION_DEPTH {
+                return Err(MacroError {
+                    code: ErrorCode::MacroTooDeep,
+                    msg: format!(
+                        "Expansion of macro {} exceeds the maximum depth of {}",
+                        name, MAX_EXPANSION_DEPTH
+                    ),
+                    loc: ast.loc,
+                    span: ast.span,
+                });
+            }
+
+            if args.len() != definition.params.len() {
+                return Err(MacroError {
+                    code: ErrorCode::MacroArgumentCount,
+                    msg: format!(
+                        "Macro {} expects {} arguments but {} were given",
+                        name,
+                        definition.params.len(),
+                        args.len()
+                    ),
+                    loc: ast.loc,
+                    span: ast.span,
+                });
+            }
+
+            // The arguments are already expanded, so the template gets placeholders in their place.
+            // They are only put in once everything is expanded, rather than expanded again.
+            let mut substitutions = HashMap::new();
+            for (param, arg) in definition.params.iter().zip(args.drain(..)) {
+                let placeholder = names.fresh(param);
+                substitutions.insert(*param, placeholder);
+                placeholders.insert(placeholder, arg);
+            }
+            let mut renames = vec![HashMap::new()];
+
+            let mut expansion = instantiate(
+                &definition.template,
+                &substitutions,
+                &mut renames,
+                ast.loc,
+                ast.span,
+                names,
+            );
+            expand(
+                &mut expansion,
+                macros,
+                names,
+                placeholders,
+                depth + 1,
+                level,
+            )?;
+            *ast = expansion;
+        }
+        Block(ref mut expressions) => {
+            macros.push_level();
+            for expression in expressions.iter_mut() {
+                expand(expression, macros, names, placeholders, depth, level + 1)?;
+            }
+            macros.remove_level();
+        }
+        MacroDefinition(name, params, template) => {
+            let definition = Macro {
+                params: params.clone(),
+                template: (**template).clone(),
+            };
+            if macros.insert(name, definition).is_err() {
+                return Err(MacroError {
+                    code: ErrorCode::MacroRedefined,
+                    msg: format!("Macro {} already defined in this scope", name),
+                    loc: ast.loc,
+                    span: ast.span,
+                });
+            }
+            ast.expr = EmptyLiteral();
+        }
+    }
+
+    Ok(())
+}
+
+// Replaces the placeholders left by expansion with the arguments they stand for. An argument can
+// itself hold placeholders, when the macro was called from another macro's template.
+fn substitute<'source>(
+    ast: &mut AstNode<'source>,
+    placeholders: &HashMap<&'source str, AstNode<'source>>,
+    level: usize,
+) -> Result<(), MacroError> {
+    while let Identifier(name) = ast.expr {
+        let Some(arg) = placeholders.get(name) else {
+            break;
+        };
+        // Arguments keep their own locations
+        *ast = arg.clone();
+    }
+    if level > MAX_TREE_DEPTH {
+        return Err(too_deep(ast));
+    }
+
+    match &mut ast.expr {
+        EmptyLiteral() | IntLiteral(_) | BoolLiteral(_) | Identifier(_) | Error(_) => (),
+        UnaryOp(_, expr)
+        | VarDeclaration(_, expr, _)
+        | Defer(expr)
+        | MacroDefinition(_, _, expr) => substitute(expr, placeholders, level + 1)?,
+        BinaryOp(left, _, right) => {
+            substitute(left, placeholders, level + 1)?;
+            substitute(right, placeholders, level + 1)?;
+        }
+        Conditional(condition_expr, then_expr, else_expr) => {
+            substitute(condition_expr, placeholders, level + 1)?;
+            substitute(then_expr, placeholders, level + 1)?;
+            if let Some(else_expr) = else_expr {
+                substitute(else_expr, placeholders, level + 1)?;
+            }
+        }
+        While(condition_expr, do_expr) => {
+            substitute(condition_expr, placeholders, level + 1)?;
+            substitute(do_expr, placeholders, level + 1)?;
+        }
+        FunCall(_, expressions) | Block(expressions) => {
+            for expression in expressions.iter_mut() {
+                substitute(expression, placeholders, level + 1)?;
+            }
+        }
+    }
+
+    ast.update_depth();
+    Ok(())
+}
+
+fn too_deep(ast: &AstNode) -> MacroError {
+    MacroError {
+        code: ErrorCode::NestingTooDeep,
+        msg: format!(
+            "Expanding macros nests expressions deeper than the maximum of {} levels",
+            MAX_TREE_DEPTH
+        ),
+        loc: ast.loc,
+        span: ast.span,
+    }
+}
+
+// Copies the template, giving it the location and span of the call site. Parameters are replaced
+// by the placeholders for their arguments.
+fn instantiate<'source>(
+    template: &AstNode<'source>,
+    substitutions: &HashMap<&'source str, &'source str>,
+    renames: &mut Vec<HashMap<&'source str, &'source str>>,
+    loc: CodeLocation,
+    span: Span,
+    names: &'source FreshNames,
+) -> AstNode<'source> {
+    macro_rules! inst {
+        ($node:expr, $renames:expr) => {
+            Box::new(instantiate(
+                $node,
+                substitutions,
+                $renames,
+                loc,
+                span,
+                names,
+            ))
+        };
+    }
+
+    let expr = match &template.expr {
+        Identifier(name) => {
+            if let Some(renamed) = renames.iter().rev().find_map(|scope| scope.get(name)) {
+                Identifier(renamed)
+            } else if let Some(placeholder) = substitutions.get(name) {
+                Identifier(placeholder)
+            } else {
+                Identifier(name)
+            }
+        }
+        UnaryOp(op, expr) => UnaryOp(op, inst!(expr, renames)),
+        BinaryOp(left, op, right) => {
+            let left = inst!(left, renames);
+            BinaryOp(left, op, inst!(right, renames))
+        }
+        VarDeclaration(name, expr, type_expr) => {
+            let expr = inst!(expr, renames);
+            let fresh_name = names.fresh(name);
+            renames
+                .last_mut()
+                .expect("Rename scopes should never be empty!")
+                .insert(name, fresh_name);
+            VarDeclaration(fresh_name, expr, *type_expr)
+        }
+        Conditional(condition_expr, then_expr, else_expr) => {
+            let condition_expr = inst!(condition_expr, renames);
+            let then_expr = inst!(then_expr, renames);
+            let else_expr = else_expr.as_ref().map(|expr| inst!(expr, renames));
+            Conditional(condition_expr, then_expr, else_expr)
+        }
+        While(condition_expr, do_expr) => {
+            let condition_expr = inst!(condition_expr, renames);
+            While(condition_expr, inst!(do_expr, renames))
+        }
+        FunCall(name, args) => FunCall(name, args.iter().map(|arg| *inst!(arg, renames)).collect()),
+        Block(expressions) => {
+            renames.push(HashMap::new());
+            let expressions = expressions
+                .iter()
+                .map(|expression| *inst!(expression, renames))
+                .collect();
+            renames.pop();
+            Block(expressions)
+        }
+        Defer(expr) => Defer(inst!(expr, renames)),
+        MacroDefinition(name, params, template) => {
+            MacroDefinition(name, params.clone(), inst!(template, renames))
+        }
+        EmptyLiteral() | IntLiteral(_) | BoolLiteral(_) | Error(_) => template.expr.clone(),
+    };
+
+    AstNode::new(loc, span, expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{
+        interpreter::interpret, parser::parse, tokenizer::tokenize, variable::Value, STACK_SIZE,
+    };
+
+    fn expand_and_interpret(code: &str) -> Value {
+        let names = FreshNames::default();
+        let tokens = tokenize(code).unwrap();
+        let mut ast = parse(&tokens).unwrap();
+        expand_macros(&mut ast, &names).unwrap();
+        interpret(&ast, &mut SymTab::new_val_table()).unwrap()
+    }
+
+    #[test]
+    fn test_expand_basic() {
+        let result = expand_and_interpret("macro double(x) { x + x }; double(21)");
+        assert_eq!(result, Value::Int(42));
+    }
+
+    #[test]
+    fn test_expand_assignment() {
+        let result = expand_and_interpret(
+            "macro swap(a, b) { var tmp = a; a = b; b = tmp; }
+            var x = 1; var y = 2; swap(x, y); x * 10 + y",
+        );
+        assert_eq!(result, Value::Int(21));
+    }
+
+    #[test]
+    fn test_expand_hygiene() {
+        let result = expand_and_interpret(
+            "macro swap(a, b) { var tmp = a; a = b; b = tmp; }
+            var tmp = 1; var y = 2; swap(tmp, y); tmp * 10 + y",
+        );
+        assert_eq!(result, Value::Int(21));
+    }
+
+    #[test]
+    fn test_expand_nested() {
+        let result = expand_and_interpret(
+            "macro double(x) { x + x }
+            macro quadruple(x) { double(double(x)) }
+            quadruple(3)",
+        );
+        assert_eq!(result, Value::Int(12));
+    }
+
+    #[test]
+    fn test_expand_scoped() {
+        let result = expand_and_interpret("{ macro one() { 1 } }; var one = 2; one");
+        assert_eq!(result, Value::Int(2));
+    }
+
+    #[test]
+    fn test_expand_call_site_location() {
+        let names = FreshNames::default();
+        let tokens = tokenize("macro one() { 1 }\n  one()").unwrap();
+        let mut ast = parse(&tokens).unwrap();
+        expand_macros(&mut ast, &names).unwrap();
+
+        let Block(expressions) = &ast.expr else {
+            panic!("Expected a block!");
+        };
+        assert_eq!(expressions[1].loc, CodeLocation::new(2, 3));
+    }
+
+    #[test]
+    fn test_expand_custom_operator() {
+        let result = expand_and_interpret(
+            "macro avg(a, b) { (a + b) / 2 }
+            infixl 5 <+> = avg;
+            10 <+> 2 * 3",
+        );
+        assert_eq!(result, Value::Int(8));
+    }
+
+    // Every macro passes its argument through two calls of the one before it, doubling the size
+    // of the expansion
+    fn doubling_macros(innermost: &str, count: usize) -> String {
+        let mut code = format!("macro a0(x) {{ {} }}\n", innermost);
+        for i in 1..=count {
+            code += &format!("macro a{}(x) {{ a{}(a{}(x)) }}\n", i, i - 1, i - 1);
+        }
+        code + &format!("a{}(1)", count)
+    }
+
+    fn with_compiler_stack(f: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_expand_arguments_once() {
+        // Every call of a macro gets one placeholder for its argument. Expanding the arguments
+        // again along with the template expanded the calls in them again as well, about five
+        // times as many at each level instead of twice.
+        let code = doubling_macros("x", 9);
+        with_compiler_stack(move || {
+            let names = FreshNames::default();
+            let tokens = tokenize(&code).unwrap();
+            let mut ast = parse(&tokens).unwrap();
+            expand_macros(&mut ast, &names).unwrap();
+
+            assert_eq!(names.counter.get(), (1 << 10) - 1);
+            let result = interpret(&ast, &mut SymTab::new_val_table()).unwrap();
+            assert_eq!(result, Value::Int(1));
+        });
+    }
+
+    #[test]
+    fn test_expand_too_deep() {
+        let code = doubling_macros("{ x }", 11);
+        with_compiler_stack(move || {
+            let names = FreshNames::default();
+            let tokens = tokenize(&code).unwrap();
+            let mut ast = parse(&tokens).unwrap();
+
+            let error = expand_macros(&mut ast, &names).unwrap_err();
+            assert_eq!(error.code, ErrorCode::NestingTooDeep);
+            assert_eq!(
+                error.msg,
+                "Expanding macros nests expressions deeper than the maximum of 2048 levels"
+            );
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_expand_wrong_arg_count() {
+        expand_and_interpret("macro double(x) { x + x }; double(1, 2)");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_expand_recursive() {
+        expand_and_interpret("macro forever(x) { forever(x) }; forever(1)");
+    }
+}
@@ -1,8 +1,9 @@
+pub mod operators;
 mod parser_utilities;
 #[cfg(test)]
 mod tests;
 
-use std::{error::Error, fmt::Display};
+use std::{collections::HashMap, error::Error, fmt::Display};
 
 use crate::compiler::{
     ast::{
@@ -10,13 +11,33 @@ use crate::compiler::{
         Expression::*,
         TypeExpression::{self},
     },
-    parser::parser_utilities::*,
-    token::{Token, TokenType},
+    diagnostic::Diagnostic,
+    error_codes::ErrorCode,
+    parser::{operators::*, parser_utilities::*},
+    suggestions::{closest_names, did_you_mean},
+    token::{CodeLocation, Span, Token, TokenType, KEYWORDS},
 };
 
 #[derive(Debug)]
 pub struct ParserError {
+    code: ErrorCode,
     msg: String,
+    loc: CodeLocation,
+    span: Span,
+    notes: Vec<String>,
+}
+
+impl ParserError {
+    // Errors point at the token that couldn't be parsed
+    fn at(code: ErrorCode, msg: String, token: &Token) -> Self {
+        Self {
+            code,
+            msg,
+            loc: token.loc,
+            span: token.span,
+            notes: Vec::new(),
+        }
+    }
 }
 
 impl Display for ParserError {
@@ -27,49 +48,282 @@ impl Display for ParserError {
 
 impl Error for ParserError {}
 
-pub fn parse<'source>(tokens: &[Token<'source>]) -> Result<AstNode<'source>, ParserError> {
-    let mut pos = 0;
+impl From<ParserError> for Diagnostic {
+    fn from(error: ParserError) -> Self {
+        let diagnostic = Diagnostic::error(error.msg, error.loc, error.span).with_code(error.code);
+        error
+            .notes
+            .into_iter()
+            .fold(diagnostic, |diagnostic, note| diagnostic.with_note(note))
+    }
+}
 
-    let first_expression = parse_block_level_expressions(&mut pos, tokens)?;
+#[derive(Debug)]
+pub struct ParserErrors {
+    pub errors: Vec<ParserError>,
+}
 
-    if pos != tokens.len() {
-        let mut expressions = vec![first_expression];
+impl Display for ParserErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors = self
+            .errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", errors.join("\n"))
+    }
+}
 
-        // Blocks don't need to be followed by a semicolon, but can be
-        if peek(&mut (pos - 1), tokens)?.text == "}" {
-            if peek(&mut pos, tokens)?.text == ";" {
-                consume_string(&mut pos, tokens, ";")?;
-            }
+impl Error for ParserErrors {}
+
+impl From<ParserErrors> for Vec<Diagnostic> {
+    fn from(errors: ParserErrors) -> Self {
+        errors.errors.into_iter().map(Diagnostic::from).collect()
+    }
+}
+
+// Enough for any handwritten program. Each level is a few frames of recursion in the parser, well
+// within the STACK_SIZE the compiler runs with.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 256;
+
+// The later passes recurse once for every level of the AST, which can get deeper than the nesting
+// of the source as chained operators nest to the left and macros expand in place. This keeps them
+// within STACK_SIZE too.
+pub const MAX_TREE_DEPTH: usize = 2048;
+
+struct ParserState<'source> {
+    // Tracks how deeply expressions are nested, so that pathological inputs are rejected instead
+    // of overflowing the stack in the parser
+    depth: usize,
+    max_depth: usize,
+    exceeded: bool,
+    // Binary operators, including any declared by the program so far
+    operators: HashMap<&'source str, Operator<'source>>,
+    // Errors recovered from so far, in the order they were found
+    errors: Vec<ParserError>,
+}
+
+impl<'source> ParserState<'source> {
+    fn enter(&mut self, pos: &mut usize, tokens: &[Token]) -> Result<(), ParserError> {
+        self.depth += 1;
+
+        if self.depth > self.max_depth {
+            self.exceeded = true;
+            let token = peek(pos, tokens)?;
+            Err(ParserError::at(
+                ErrorCode::NestingTooDeep,
+                format!(
+                    "Expressions nested deeper than the maximum of {} levels",
+                    self.max_depth
+                ),
+                &token,
+            ))
         } else {
-            consume_string(&mut pos, tokens, ";")?;
+            Ok(())
+        }
+    }
+
+    fn exit(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+pub fn parse<'source>(tokens: &[Token<'source>]) -> Result<AstNode<'source>, ParserErrors> {
+    parse_with_max_depth(tokens, DEFAULT_MAX_NESTING_DEPTH)
+}
+
+pub fn parse_with_max_depth<'source>(
+    tokens: &[Token<'source>],
+    max_depth: usize,
+) -> Result<AstNode<'source>, ParserErrors> {
+    let (ast, errors) = parse_with_recovery(tokens, max_depth);
+
+    if errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(ParserErrors { errors })
+    }
+}
+
+// Always produces an AST, even for invalid input. Anything that couldn't be parsed is replaced by
+// an error node, and the errors are returned in source order.
+pub fn parse_with_recovery<'source>(
+    tokens: &[Token<'source>],
+    max_depth: usize,
+) -> (AstNode<'source>, Vec<ParserError>) {
+    let Some(first_token) = tokens.first() else {
+        let msg = String::from("Input to parser appears to be empty!");
+        let ast = AstNode::new(CodeLocation::new(1, 1), Span::default(), Error(msg.clone()));
+        let error = ParserError {
+            code: ErrorCode::EmptyInput,
+            msg,
+            loc: ast.loc,
+            span: ast.span,
+            notes: Vec::new(),
+        };
+        return (ast, vec![error]);
+    };
+
+    let mut pos = 0;
+    let mut state = ParserState {
+        depth: 0,
+        max_depth,
+        exceeded: false,
+        operators: builtin_operators(),
+        errors: Vec::new(),
+    };
+
+    // Nothing useful can be recovered from input nested too deeply
+    let mut expressions = match parse_sequence(&mut pos, tokens, false, &mut state) {
+        Ok(expressions) => expressions,
+        Err(error) => {
+            let ast = AstNode::new(first_token.loc, first_token.span, Error(error.msg.clone()));
+            return (ast, vec![error]);
         }
+    };
+
+    let ast = if expressions.len() == 1 {
+        expressions.remove(0)
+    } else {
+        let last_token = &tokens[tokens.len() - 1];
+        let span = first_token.span.to(last_token.span);
+        AstNode::new(first_token.loc, span, Block(expressions))
+    };
 
-        while peek(&mut pos, tokens)?.token_type != TokenType::End {
-            expressions.push(parse_block_level_expressions(&mut pos, tokens)?);
+    (ast, state.errors)
+}
+
+// Parses semicolon separated expressions up to the end of the block or the input. On an error,
+// an error node is left in place of the expression and parsing resumes at the next statement.
+// Exceeding the nesting limit is the only error that isn't recovered from.
+fn parse_sequence<'source>(
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    in_block: bool,
+    state: &mut ParserState<'source>,
+) -> Result<Vec<AstNode<'source>>, ParserError> {
+    let at_end = |pos: &mut usize| {
+        let token = peek(pos, tokens).expect("Tokens should never be empty!");
+        token.token_type == TokenType::End || (in_block && token.text == "}")
+    };
 
-            if peek(&mut pos, tokens)?.token_type == TokenType::End {
-                break;
+    let mut expressions = Vec::new();
+    while !at_end(pos) {
+        let start = *pos;
+        let depth = state.depth;
+        let expression = if in_block {
+            parse_block_expression(pos, tokens, state)
+        } else {
+            parse_block_level_expressions(pos, tokens, state)
+        };
+
+        match expression {
+            Ok(expression) => expressions.push(expression),
+            Err(error) if state.exceeded => return Err(error),
+            Err(error) => {
+                state.depth = depth;
+                expressions.push(recover(error, start, pos, tokens, in_block, state));
+                continue;
             }
+        }
 
-            // Blocks don't need to be followed by a semicolon, but can be
-            if peek(&mut (pos - 1), tokens)?.text == "}" {
-                if peek(&mut pos, tokens)?.text == ";" {
-                    consume_string(&mut pos, tokens, ";")?;
-                }
+        // Last expression left as return expression, if no semicolon is present
+        if at_end(pos) {
+            break;
+        }
+
+        // Blocks don't need to be followed by a semicolon, but can be
+        let start = *pos;
+        if peek(pos, tokens).unwrap().text == ";" {
+            consume_string(pos, tokens, ";").unwrap();
+        } else if tokens[*pos - 1].text != "}" {
+            let error = consume_string(pos, tokens, ";").unwrap_err();
+            expressions.push(recover(error, start, pos, tokens, in_block, state));
+            continue;
+        }
+
+        // If the last expression ended in a semicolon, empty return
+        if at_end(pos) {
+            let (loc, span) = if in_block {
+                // The block may be missing its closing brace, leaving only the end of input
+                let next_token = peek(pos, tokens)?;
+                (
+                    next_token.loc,
+                    Span::new(next_token.span.start, next_token.span.start),
+                )
             } else {
-                consume_string(&mut pos, tokens, ";")?;
-            }
+                let last_token = &tokens[*pos - 1];
+                (
+                    last_token.loc,
+                    Span::new(last_token.span.end, last_token.span.end),
+                )
+            };
+            expressions.push(AstNode::new(loc, span, EmptyLiteral()));
         }
+    }
 
-        let last_token = peek(&mut (pos - 1), tokens)?;
-        if last_token.text == ";" {
-            expressions.push(AstNode::new(last_token.loc, EmptyLiteral()));
+    Ok(expressions)
+}
+
+// Skips the unexpected token and continues to the next semicolon, closing brace or statement
+// keyword, returning an error node that covers the skipped tokens
+fn recover<'source>(
+    error: ParserError,
+    start: usize,
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    in_block: bool,
+    state: &mut ParserState<'source>,
+) -> AstNode<'source> {
+    const STATEMENT_KEYWORDS: [&str; 8] = [
+        "var", "if", "while", "defer", "macro", "infixl", "infixr", "infix",
+    ];
+
+    *pos = (*pos).max(start);
+    let error_pos = *pos;
+    let error = suggest_keyword(error, &tokens[..(error_pos + 1).min(tokens.len())]);
+    while let Some(token) = tokens.get(*pos) {
+        if token.text == ";" || (!in_block && token.text == "}") {
+            *pos += 1;
+            break;
+        } else if token.text == "}"
+            || (*pos > error_pos && STATEMENT_KEYWORDS.contains(&token.text))
+        {
+            break;
         }
+        *pos += 1;
+    }
 
-        Ok(AstNode::new(tokens[0].loc, Block(expressions)))
-    } else {
-        Ok(first_expression)
+    let first_token = &tokens[start.min(tokens.len() - 1)];
+    let last_token = &tokens[(*pos).max(1) - 1];
+    let ast = AstNode::new(
+        first_token.loc,
+        first_token.span.to(last_token.span),
+        Error(error.msg.clone()),
+    );
+    state.errors.push(error);
+    ast
+}
+
+// Shorter names, like the `f` in `fun f(x: Int)`, are one edit away from keywords like `if` or `do`
+// without being misspellings of them
+const MIN_MISSPELT_KEYWORD_LEN: usize = 3;
+
+// A misspelt keyword is read as an identifier, so the error is at the misspelling or right after
+// it. The tokens given end at the token the error is at.
+fn suggest_keyword(mut error: ParserError, tokens: &[Token]) -> ParserError {
+    let misspelt = tokens
+        .iter()
+        .rev()
+        .take(2)
+        .filter(|token| token.token_type == TokenType::Identifier)
+        .filter(|token| token.text.chars().count() >= MIN_MISSPELT_KEYWORD_LEN)
+        .find_map(|token| did_you_mean(&closest_names(token.text, KEYWORDS, 1)));
+
+    if let Some(note) = misspelt {
+        error.notes.push(note);
     }
+    error
 }
 
 // Horrible name, basically used to get the full expressions contained
@@ -77,118 +331,217 @@ pub fn parse<'source>(tokens: &[Token<'source>]) -> Result<AstNode<'source>, Par
 fn parse_block_level_expressions<'source>(
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
-    // Special handling for variable declaration, since it is only allowed in very specifc places
-    if peek(pos, tokens)?.text == "var" {
-        parse_var_declaration(pos, tokens)
+    // Special handling for variable and macro declarations, since they are only allowed in very
+    // specifc places
+    match peek(pos, tokens)?.text {
+        "var" => parse_var_declaration(pos, tokens, state),
+        "macro" => parse_macro_definition(pos, tokens, state),
+        "infixl" | "infixr" | "infix" => parse_operator_declaration(pos, tokens, state),
+        _ => parse_expression(pos, tokens, state),
+    }
+}
+
+// Deferred expressions are only valid as direct children of a block
+fn parse_block_expression<'source>(
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
+) -> Result<AstNode<'source>, ParserError> {
+    if peek(pos, tokens)?.text == "defer" {
+        parse_defer(pos, tokens, state)
     } else {
-        parse_expression(0, pos, tokens)
+        parse_block_level_expressions(pos, tokens, state)
     }
 }
 
 fn parse_expression<'source>(
-    level: usize,
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
-    const OPS: [&[&str]; 8] = [
-        &["="],                  // 0
-        &["or"],                 // 1
-        &["and"],                // 2
-        &["==", "!="],           // 3
-        &["<", "<=", ">=", ">"], // 4
-        &["+", "-"],             // 5
-        &["*", "/", "%"],        // 6
-        &["not", "-"],           // 7
-                                 // 8, everything not explicitly listed above goes here
-    ];
+    // Every nested expression passes through here
+    state.enter(pos, tokens)?;
+    let expression = parse_binary_operation(0, pos, tokens, state)?;
+    state.exit();
 
-    match level {
-        0 => {
-            let left = parse_expression(level + 1, pos, tokens)?;
-            if OPS[level].contains(&peek(pos, tokens)?.text) {
-                let operator_token = consume_strings(pos, tokens, OPS[level])?;
-                let right = parse_expression(level, pos, tokens)?;
-                Ok(AstNode::new(
-                    operator_token.loc,
-                    BinaryOp(Box::new(left), operator_token.text, Box::new(right)),
-                ))
-            } else {
-                Ok(left)
-            }
+    Ok(expression)
+}
+
+// Precedence climbing, parsing operators that bind at least as tightly as the given precedence
+fn parse_binary_operation<'source>(
+    min_precedence: usize,
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
+) -> Result<AstNode<'source>, ParserError> {
+    let mut left = parse_unary_operation(pos, tokens, state)?;
+
+    while let Some(operator) = peek_operator(pos, tokens, state)? {
+        if operator.precedence < min_precedence {
+            break;
         }
-        1..=6 => {
-            let mut left = parse_expression(level + 1, pos, tokens)?;
-            while OPS[level].contains(&peek(pos, tokens)?.text) {
-                let operator_token = consume_strings(pos, tokens, OPS[level])?;
-                let right = parse_expression(level + 1, pos, tokens)?;
-
-                left = AstNode::new(
-                    operator_token.loc,
-                    BinaryOp(Box::new(left), operator_token.text, Box::new(right)),
-                );
-            }
-            Ok(left)
+
+        let operator_token = consume(pos, tokens)?;
+        let right_precedence = match operator.associativity {
+            Associativity::Right => operator.precedence,
+            Associativity::Left | Associativity::None => operator.precedence + 1,
+        };
+        // Only the right operand is parsed recursively, chains of left associative operators
+        // are built by this loop
+        state.enter(pos, tokens)?;
+        let right = parse_binary_operation(right_precedence, pos, tokens, state)?;
+        state.exit();
+
+        let span = left.span.to(right.span);
+        let expr = match operator.function {
+            Some(function) => FunCall(function, vec![left, right]),
+            None => BinaryOp(Box::new(left), operator_token.text, Box::new(right)),
+        };
+        left = AstNode::new(operator_token.loc, span, expr);
+        if left.depth > MAX_TREE_DEPTH {
+            state.exceeded = true;
+            return Err(ParserError::at(
+                ErrorCode::NestingTooDeep,
+                format!(
+                    "Expression nested deeper than the maximum of {} levels",
+                    MAX_TREE_DEPTH
+                ),
+                &operator_token,
+            ));
         }
-        7 => {
-            if OPS[level].contains(&peek(pos, tokens)?.text) {
-                let operator_token = consume_strings(pos, tokens, OPS[level])?;
-                let right = parse_expression(level, pos, tokens)?;
-                Ok(AstNode::new(
-                    operator_token.loc,
-                    UnaryOp(operator_token.text, Box::new(right)),
-                ))
-            } else {
-                parse_expression(level + 1, pos, tokens)
+
+        if operator.associativity == Associativity::None {
+            if let Some(next) = peek_operator(pos, tokens, state)? {
+                if next.precedence == operator.precedence {
+                    let next_token = peek(pos, tokens)?;
+                    return Err(ParserError::at(
+                        ErrorCode::ChainedNonAssociative,
+                        format!(
+                            "Non-associative operator {} can't be chained with {}",
+                            operator_token.describe(),
+                            next_token.describe()
+                        ),
+                        &next_token,
+                    ));
+                }
             }
         }
-        8 => parse_term(pos, tokens),
-        _ => unreachable!(),
     }
+
+    Ok(left)
+}
+
+fn parse_unary_operation<'source>(
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
+) -> Result<AstNode<'source>, ParserError> {
+    if UNARY_OPERATORS.contains(&peek(pos, tokens)?.text) {
+        state.enter(pos, tokens)?;
+        let operator_token = consume_strings(pos, tokens, &UNARY_OPERATORS)?;
+        let right = parse_unary_operation(pos, tokens, state)?;
+        state.exit();
+
+        Ok(AstNode::new(
+            operator_token.loc,
+            operator_token.span.to(right.span),
+            UnaryOp(operator_token.text, Box::new(right)),
+        ))
+    } else {
+        parse_term(pos, tokens, state)
+    }
+}
+
+// Only operator and keyword tokens can be binary operators, the rest end the expression
+fn peek_operator<'source>(
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    state: &ParserState<'source>,
+) -> Result<Option<Operator<'source>>, ParserError> {
+    let token = peek(pos, tokens)?;
+
+    Ok(match token.token_type {
+        TokenType::Operator | TokenType::Keyword => state.operators.get(token.text).copied(),
+        _ => None,
+    })
 }
 
 fn parse_term<'source>(
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
     let token = peek(pos, tokens)?;
 
     match token.token_type {
         TokenType::Integer => Ok(parse_int_literal(pos, tokens)?),
-        TokenType::Identifier => match token.text {
-            "if" => parse_conditional(pos, tokens),
-            "while" => parse_while_loop(pos, tokens),
+        TokenType::Keyword => match token.text {
+            "if" => parse_conditional(pos, tokens, state),
+            "while" => parse_while_loop(pos, tokens, state),
             "true" | "false" => parse_bool_literal(pos, tokens),
-            "var" => Err(ParserError {
-                msg: format!("Invalid variable declaration {}", token),
-            }),
-            _ => {
-                if peek(&mut (*pos + 1), tokens)?.text == "(" {
-                    parse_function(pos, tokens)
-                } else {
-                    parse_identifier(pos, tokens)
-                }
-            }
+            "var" => Err(ParserError::at(
+                ErrorCode::MisplacedStatement,
+                String::from(
+                    "Variables can only be declared directly in a block or at the top level",
+                ),
+                &token,
+            )),
+            "defer" => Err(ParserError::at(
+                ErrorCode::MisplacedStatement,
+                String::from("Defer is only allowed directly in a block"),
+                &token,
+            )),
+            "macro" => Err(ParserError::at(
+                ErrorCode::MisplacedStatement,
+                String::from("Macros can only be defined directly in a block or at the top level"),
+                &token,
+            )),
+            "infixl" | "infixr" | "infix" => Err(ParserError::at(
+                ErrorCode::MisplacedStatement,
+                String::from(
+                    "Operators can only be declared directly in a block or at the top level",
+                ),
+                &token,
+            )),
+            _ => Err(ParserError::at(
+                ErrorCode::UnexpectedToken,
+                format!("Unexpected {}", token.describe()),
+                &token,
+            )),
         },
+        TokenType::Identifier => {
+            if peek(&mut (*pos + 1), tokens)?.text == "(" {
+                parse_function(pos, tokens, state)
+            } else {
+                parse_identifier(pos, tokens)
+            }
+        }
         TokenType::Punctuation => match token.text {
-            "(" => parse_parenthesized(pos, tokens),
-            "{" => parse_block(pos, tokens),
-            _ => Err(ParserError {
-                msg: format!("Unexpected {}", token),
-            }),
+            "(" => parse_parenthesized(pos, tokens, state),
+            "{" => parse_block(pos, tokens, state),
+            _ => Err(ParserError::at(
+                ErrorCode::UnexpectedToken,
+                format!("Unexpected {}", token.describe()),
+                &token,
+            )),
         },
-        _ => Err(ParserError {
-            msg: format!("Unexpected {}", token),
-        }),
+        _ => Err(ParserError::at(
+            ErrorCode::UnexpectedToken,
+            format!("Unexpected {}", token.describe()),
+            &token,
+        )),
     }
 }
 
 fn parse_var_declaration<'source>(
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
-    consume_string(pos, tokens, "var")?;
-    let name_token = consume_type(pos, tokens, TokenType::Identifier)?;
+    let start = consume_string(pos, tokens, "var")?;
+    let name_token = consume_name(pos, tokens, "variable")?;
 
     let mut type_expr = None;
     if peek(pos, tokens)?.text == ":" {
@@ -196,43 +549,148 @@ fn parse_var_declaration<'source>(
 
         let type_token = consume_type(pos, tokens, TokenType::Identifier)?;
         type_expr = match type_token.text {
-            "Int" => Some(TypeExpression::Int(type_token.loc)),
-            "Bool" => Some(TypeExpression::Bool(type_token.loc)),
+            "Int" => Some(TypeExpression::Int(type_token.loc, type_token.span)),
+            "Bool" => Some(TypeExpression::Bool(type_token.loc, type_token.span)),
             _ => {
-                return Err(ParserError {
-                    msg: format!("Invalid type specifier {}", type_token.text),
-                });
+                return Err(ParserError::at(
+                    ErrorCode::InvalidTypeSpecifier,
+                    format!("Invalid type specifier {}", type_token.text),
+                    &type_token,
+                ));
             }
         }
     }
 
     consume_string(pos, tokens, "=")?;
-    let value = parse_expression(0, pos, tokens)?;
+    let value = parse_expression(pos, tokens, state)?;
     Ok(AstNode::new(
-        name_token.loc,
+        start.loc,
+        start.span.to(value.span),
         VarDeclaration(name_token.text, Box::new(value), type_expr),
     ))
 }
 
+fn parse_macro_definition<'source>(
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
+) -> Result<AstNode<'source>, ParserError> {
+    let start = consume_string(pos, tokens, "macro")?;
+    let name_token = consume_name(pos, tokens, "macro")?;
+    consume_string(pos, tokens, "(")?;
+
+    let mut params = Vec::new();
+    // If/loop used instead of while to show that we will always use break to exit the loop
+    if peek(pos, tokens)?.text != ")" {
+        loop {
+            params.push(consume_name(pos, tokens, "parameter")?.text);
+
+            match peek(pos, tokens)?.text {
+                "," => consume_string(pos, tokens, ",")?,
+                _ => break, // Break out of the loop. Intentionally causes an error with a missing comma
+            };
+        }
+    }
+    consume_string(pos, tokens, ")")?;
+
+    let template = Box::new(parse_block(pos, tokens, state)?);
+    Ok(AstNode::new(
+        start.loc,
+        start.span.to(template.span),
+        MacroDefinition(name_token.text, params, template),
+    ))
+}
+
+// Declared operators apply to the rest of the program, even outside the block they are declared
+// in, and are replaced by calls to the function they are bound to
+fn parse_operator_declaration<'source>(
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
+) -> Result<AstNode<'source>, ParserError> {
+    let start = consume_strings(pos, tokens, &["infixl", "infixr", "infix"])?;
+    let associativity = match start.text {
+        "infixl" => Associativity::Left,
+        "infixr" => Associativity::Right,
+        _ => Associativity::None,
+    };
+
+    let precedence_token = consume_type(pos, tokens, TokenType::Integer)?;
+    let precedence = match precedence_token.text.parse::<usize>() {
+        Ok(precedence) if CUSTOM_PRECEDENCES.contains(&precedence) => precedence,
+        _ => {
+            return Err(ParserError::at(
+                ErrorCode::InvalidPrecedence,
+                format!(
+                    "Operator precedence must be between {} and {} but found {}",
+                    CUSTOM_PRECEDENCES.start(),
+                    CUSTOM_PRECEDENCES.end(),
+                    precedence_token.describe()
+                ),
+                &precedence_token,
+            ))
+        }
+    };
+
+    let operator_token = consume_type(pos, tokens, TokenType::Operator)?;
+    if let Some(existing) = state.operators.get(operator_token.text) {
+        let kind = if existing.function.is_some() {
+            "already declared"
+        } else {
+            "built in"
+        };
+        return Err(ParserError::at(
+            ErrorCode::OperatorRedeclared,
+            format!(
+                "Operator {} is {} and can't be redeclared",
+                operator_token.describe(),
+                kind
+            ),
+            &operator_token,
+        ));
+    }
+
+    consume_string(pos, tokens, "=")?;
+    let function = consume_name(pos, tokens, "function")?;
+
+    state.operators.insert(
+        operator_token.text,
+        Operator {
+            precedence,
+            associativity,
+            function: Some(function.text),
+        },
+    );
+
+    Ok(AstNode::new(
+        start.loc,
+        start.span.to(function.span),
+        EmptyLiteral(),
+    ))
+}
+
 fn parse_conditional<'source>(
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
     let start = consume_string(pos, tokens, "if")?;
-    let condition = Box::new(parse_expression(0, pos, tokens)?);
+    let condition = Box::new(parse_expression(pos, tokens, state)?);
     consume_string(pos, tokens, "then")?;
-    let then_expr = Box::new(parse_expression(0, pos, tokens)?);
+    let then_expr = Box::new(parse_expression(pos, tokens, state)?);
 
     let else_expr = match peek(pos, tokens)?.text {
         "else" => {
             consume_string(pos, tokens, "else")?;
-            Some(Box::new(parse_expression(0, pos, tokens)?))
+            Some(Box::new(parse_expression(pos, tokens, state)?))
         }
         _ => None,
     };
 
+    let end_span = else_expr.as_ref().unwrap_or(&then_expr).span;
     Ok(AstNode::new(
         start.loc,
+        start.span.to(end_span),
         Conditional(condition, then_expr, else_expr),
     ))
 }
@@ -240,64 +698,79 @@ fn parse_conditional<'source>(
 fn parse_while_loop<'source>(
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
     let start = consume_string(pos, tokens, "while")?;
-    let condition = Box::new(parse_expression(0, pos, tokens)?);
+    let condition = Box::new(parse_expression(pos, tokens, state)?);
     consume_string(pos, tokens, "do")?;
-    let do_expr = Box::new(parse_expression(0, pos, tokens)?);
+    let do_expr = Box::new(parse_expression(pos, tokens, state)?);
 
-    Ok(AstNode::new(start.loc, While(condition, do_expr)))
+    Ok(AstNode::new(
+        start.loc,
+        start.span.to(do_expr.span),
+        While(condition, do_expr),
+    ))
+}
+
+fn parse_defer<'source>(
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
+) -> Result<AstNode<'source>, ParserError> {
+    let start = consume_string(pos, tokens, "defer")?;
+    let expression = Box::new(parse_expression(pos, tokens, state)?);
+
+    Ok(AstNode::new(
+        start.loc,
+        start.span.to(expression.span),
+        Defer(expression),
+    ))
 }
 
 fn parse_parenthesized<'source>(
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
-    consume_string(pos, tokens, "(")?;
-    let expression = parse_expression(0, pos, tokens)?;
-    consume_string(pos, tokens, ")")?;
+    let start = consume_string(pos, tokens, "(")?;
+    let mut expression = parse_expression(pos, tokens, state)?;
+    let end = consume_string(pos, tokens, ")")?;
+
+    // The parentheses are part of the source range of the expression
+    expression.span = start.span.to(end.span);
     Ok(expression)
 }
 
 fn parse_block<'source>(
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
     let start = consume_string(pos, tokens, "{")?;
-
-    let mut expressions = Vec::new();
-    while peek(pos, tokens)?.text != "}" {
-        expressions.push(parse_block_level_expressions(pos, tokens)?);
-
-        // Last expression left as return expression, if no semicolon is present
-        if peek(pos, tokens)?.text == "}" {
-            break;
-        }
-
-        // Blocks don't need to be followed by a semicolon, but can be
-        if peek(&mut (*pos - 1), tokens)?.text == "}" {
-            if peek(pos, tokens)?.text == ";" {
-                consume_string(pos, tokens, ";")?;
-            }
-        } else {
-            consume_string(pos, tokens, ";")?;
+    let mut expressions = parse_sequence(pos, tokens, true, state)?;
+
+    // A missing closing brace is recorded in the block, so the errors inside it aren't lost
+    let end_span = match consume_string(pos, tokens, "}") {
+        Ok(end) => end.span,
+        Err(error) => {
+            let end = peek(pos, tokens)?;
+            expressions.push(AstNode::new(end.loc, end.span, Error(error.msg.clone())));
+            state.errors.push(error);
+            end.span
         }
+    };
 
-        // If the last expression of the block ended in a semicolon, empty return
-        let next_token = peek(pos, tokens)?;
-        if next_token.text == "}" {
-            expressions.push(AstNode::new(next_token.loc, EmptyLiteral()));
-            break;
-        }
-    }
-
-    consume_string(pos, tokens, "}")?;
-    Ok(AstNode::new(start.loc, Block(expressions)))
+    Ok(AstNode::new(
+        start.loc,
+        start.span.to(end_span),
+        Block(expressions),
+    ))
 }
 
 fn parse_function<'source>(
     pos: &mut usize,
     tokens: &[Token<'source>],
+    state: &mut ParserState<'source>,
 ) -> Result<AstNode<'source>, ParserError> {
     let identifier = consume_type(pos, tokens, TokenType::Identifier)?;
     consume_string(pos, tokens, "(")?;
@@ -306,7 +779,7 @@ fn parse_function<'source>(
     // If/loop used instead of while to show that we will always use break to exit the loop
     if peek(pos, tokens)?.text != ")" {
         loop {
-            arguments.push(parse_expression(0, pos, tokens)?);
+            arguments.push(parse_expression(pos, tokens, state)?);
 
             match peek(pos, tokens)?.text {
                 "," => consume_string(pos, tokens, ",")?,
@@ -314,9 +787,10 @@ fn parse_function<'source>(
             };
         }
     }
-    consume_string(pos, tokens, ")")?;
+    let end = consume_string(pos, tokens, ")")?;
     Ok(AstNode::new(
         identifier.loc,
+        identifier.span.to(end.span),
         FunCall(identifier.text, arguments),
     ))
 }
@@ -327,35 +801,47 @@ fn parse_int_literal<'source>(
 ) -> Result<AstNode<'source>, ParserError> {
     let token = consume_type(pos, tokens, TokenType::Integer)?;
 
-    let expr = match token.text.parse::<i128>() {
+    let digits = token.text.replace('_', "");
+    let parsed = match digits.get(..2) {
+        Some("0x") => i128::from_str_radix(&digits[2..], 16),
+        Some("0b") => i128::from_str_radix(&digits[2..], 2),
+        Some("0o") => i128::from_str_radix(&digits[2..], 8),
+        _ => digits.parse::<i128>(),
+    };
+
+    let expr = match parsed {
         Ok(val) => IntLiteral(val),
         Err(_) => {
-            return Err(ParserError {
-                msg: format!("Invalid value in token {token}"),
-            });
+            return Err(ParserError::at(
+                ErrorCode::InvalidIntegerLiteral,
+                format!("Invalid integer literal {}", token.describe()),
+                &token,
+            ));
         }
     };
 
-    Ok(AstNode::new(token.loc, expr))
+    Ok(AstNode::new(token.loc, token.span, expr))
 }
 
 fn parse_bool_literal<'source>(
     pos: &mut usize,
     tokens: &[Token],
 ) -> Result<AstNode<'source>, ParserError> {
-    let token = consume_type(pos, tokens, TokenType::Identifier)?;
+    let token = consume_type(pos, tokens, TokenType::Keyword)?;
 
     let expr = match token.text {
         "true" => BoolLiteral(true),
         "false" => BoolLiteral(false),
         _ => {
-            return Err(ParserError {
-                msg: format!("Expected bool literal but found {token}"),
-            })
+            return Err(ParserError::at(
+                ErrorCode::ExpectedToken,
+                format!("Expected true or false but found {}", token.describe()),
+                &token,
+            ))
         }
     };
 
-    Ok(AstNode::new(token.loc, expr))
+    Ok(AstNode::new(token.loc, token.span, expr))
 }
 
 fn parse_identifier<'source>(
@@ -363,5 +849,5 @@ fn parse_identifier<'source>(
     tokens: &[Token<'source>],
 ) -> Result<AstNode<'source>, ParserError> {
     let token = consume_type(pos, tokens, TokenType::Identifier)?;
-    Ok(AstNode::new(token.loc, Identifier(token.text)))
+    Ok(AstNode::new(token.loc, token.span, Identifier(token.text)))
 }
@@ -0,0 +1,54 @@
+use std::{collections::HashMap, ops::RangeInclusive};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Associativity {
+    Left,
+    Right,
+    // Can't be chained with operators of the same precedence
+    None,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Operator<'source> {
+    pub precedence: usize,
+    pub associativity: Associativity,
+    // Declared operators are calls to a function, built in ones are kept as binary operations
+    pub function: Option<&'source str>,
+}
+
+// Higher precedences bind more tightly, and unary operators bind more tightly than all of them
+const BUILTIN_OPERATORS: [(&str, usize, Associativity); 14] = [
+    ("=", 0, Associativity::Right),
+    ("or", 1, Associativity::Left),
+    ("and", 2, Associativity::Left),
+    ("==", 3, Associativity::Left),
+    ("!=", 3, Associativity::Left),
+    ("<", 4, Associativity::Left),
+    ("<=", 4, Associativity::Left),
+    (">", 4, Associativity::Left),
+    (">=", 4, Associativity::Left),
+    ("+", 5, Associativity::Left),
+    ("-", 5, Associativity::Left),
+    ("*", 6, Associativity::Left),
+    ("/", 6, Associativity::Left),
+    ("%", 6, Associativity::Left),
+];
+
+pub const UNARY_OPERATORS: [&str; 2] = ["not", "-"];
+
+// Declared operators can't bind as loosely as assignment
+pub const CUSTOM_PRECEDENCES: RangeInclusive<usize> = 1..=9;
+
+pub fn builtin_operators<'source>() -> HashMap<&'source str, Operator<'source>> {
+    BUILTIN_OPERATORS
+        .iter()
+        .map(|&(symbol, precedence, associativity)| {
+            let operator = Operator {
+                precedence,
+                associativity,
+                function: None,
+            };
+            (symbol, operator)
+        })
+        .collect()
+}
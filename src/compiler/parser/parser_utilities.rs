@@ -13,14 +13,21 @@ pub fn consume_strings<'source>(
     tokens: &[Token<'source>],
     strings: &[&str],
 ) -> Result<Token<'source>, ParserError> {
-    let token = consume(pos, tokens)?;
+    let token = peek(pos, tokens)?;
 
+    // The position is only advanced on a match, so errors point at the unexpected token
     if strings.contains(&token.text) {
-        Ok(token)
+        consume(pos, tokens)
     } else {
-        Err(ParserError {
-            msg: format!("Expected one of {:?} but found {}", strings, token),
-        })
+        Err(ParserError::at(
+            ErrorCode::ExpectedToken,
+            format!(
+                "Expected {} but found {}",
+                alternatives(strings.iter().map(|string| format!("`{}`", string))),
+                token.describe()
+            ),
+            &token,
+        ))
     }
 }
 
@@ -37,14 +44,56 @@ pub fn consume_types<'source>(
     tokens: &[Token<'source>],
     types: &[TokenType],
 ) -> Result<Token<'source>, ParserError> {
-    let token = consume(pos, tokens)?;
+    let token = peek(pos, tokens)?;
 
     if types.contains(&token.token_type) {
-        Ok(token)
+        consume(pos, tokens)
     } else {
-        Err(ParserError {
-            msg: format!("Expected one of {:?} but found {}", types, token),
-        })
+        Err(ParserError::at(
+            ErrorCode::ExpectedToken,
+            format!(
+                "Expected {} but found {}",
+                alternatives(
+                    types
+                        .iter()
+                        .map(|token_type| token_type.describe().to_string())
+                ),
+                token.describe()
+            ),
+            &token,
+        ))
+    }
+}
+
+// Keywords are rejected with a clearer message than a plain token type mismatch
+pub fn consume_name<'source>(
+    pos: &mut usize,
+    tokens: &[Token<'source>],
+    usage: &str,
+) -> Result<Token<'source>, ParserError> {
+    let token = peek(pos, tokens)?;
+
+    if token.token_type == TokenType::Keyword {
+        Err(ParserError::at(
+            ErrorCode::KeywordAsName,
+            format!(
+                "Reserved keyword {} can't be used as a {} name",
+                token.describe(),
+                usage
+            ),
+            &token,
+        ))
+    } else {
+        consume_type(pos, tokens, TokenType::Identifier)
+    }
+}
+
+// Lists what was expected as `a`, `b` or `c`
+fn alternatives(items: impl Iterator<Item = String>) -> String {
+    let mut items = items.collect::<Vec<_>>();
+    match items.pop() {
+        Some(last) if !items.is_empty() => format!("{} or {}", items.join(", "), last),
+        last => last.unwrap_or_default(),
     }
 }
 
@@ -64,10 +113,19 @@ pub fn peek<'source>(
     if let Some(token) = tokens.get(*pos) {
         Ok(token.clone())
     } else if let Some(last_token) = tokens.get(*pos - 1) {
-        Ok(Token::new("", TokenType::End, last_token.loc))
+        Ok(Token::new(
+            "",
+            TokenType::End,
+            last_token.loc,
+            Span::new(last_token.span.end, last_token.span.end),
+        ))
     } else {
         Err(ParserError {
+            code: ErrorCode::EmptyInput,
             msg: String::from("Input to parser appears to be empty!"),
+            loc: CodeLocation::new(1, 1),
+            span: Span::default(),
+            notes: Vec::new(),
         })
     }
 }
@@ -1,9 +1,49 @@
 use super::*;
-use crate::compiler::{token::CodeLocation, tokenizer::tokenize};
+use crate::compiler::{
+    token::{CodeLocation, Span},
+    tokenizer::tokenize,
+    STACK_SIZE,
+};
+
+// Resets every location and span, so trees can be compared by structure alone
+fn strip_locations<'source>(ast: AstNode<'source>) -> AstNode<'source> {
+    let strip = |node: Box<AstNode<'source>>| Box::new(strip_locations(*node));
+    let strip_type = |type_expr: Option<TypeExpression>| {
+        type_expr.map(|type_expr| match type_expr {
+            TypeExpression::Int(..) => {
+                TypeExpression::Int(CodeLocation::default(), Span::default())
+            }
+            TypeExpression::Bool(..) => {
+                TypeExpression::Bool(CodeLocation::default(), Span::default())
+            }
+        })
+    };
+
+    let expr = match ast.expr {
+        UnaryOp(op, expr) => UnaryOp(op, strip(expr)),
+        BinaryOp(left, op, right) => BinaryOp(strip(left), op, strip(right)),
+        VarDeclaration(name, expr, type_expr) => {
+            VarDeclaration(name, strip(expr), strip_type(type_expr))
+        }
+        Conditional(condition_expr, then_expr, else_expr) => Conditional(
+            strip(condition_expr),
+            strip(then_expr),
+            else_expr.map(strip),
+        ),
+        While(condition_expr, do_expr) => While(strip(condition_expr), strip(do_expr)),
+        FunCall(name, args) => FunCall(name, args.into_iter().map(strip_locations).collect()),
+        Block(expressions) => Block(expressions.into_iter().map(strip_locations).collect()),
+        Defer(expr) => Defer(strip(expr)),
+        MacroDefinition(name, params, template) => MacroDefinition(name, params, strip(template)),
+        expr @ (EmptyLiteral() | IntLiteral(_) | BoolLiteral(_) | Identifier(_) | Error(_)) => expr,
+    };
+
+    AstNode::new(CodeLocation::default(), Span::default(), expr)
+}
 
 macro_rules! bool_ast {
     ($x:expr) => {
-        AstNode::new(CodeLocation::new(usize::MAX, usize::MAX), BoolLiteral($x))
+        AstNode::new(CodeLocation::default(), Span::default(), BoolLiteral($x))
     };
 }
 
@@ -15,7 +55,7 @@ macro_rules! bool_ast_b {
 
 macro_rules! int_ast {
     ($x:expr) => {
-        AstNode::new(CodeLocation::new(usize::MAX, usize::MAX), IntLiteral($x))
+        AstNode::new(CodeLocation::default(), Span::default(), IntLiteral($x))
     };
 }
 
@@ -27,7 +67,7 @@ macro_rules! int_ast_b {
 
 macro_rules! id_ast {
     ($x:expr) => {
-        AstNode::new(CodeLocation::new(usize::MAX, usize::MAX), Identifier($x))
+        AstNode::new(CodeLocation::default(), Span::default(), Identifier($x))
     };
 }
 
@@ -39,7 +79,7 @@ macro_rules! id_ast_b {
 
 macro_rules! un_ast {
     ($x:expr, $y:expr) => {
-        AstNode::new(CodeLocation::new(usize::MAX, usize::MAX), UnaryOp($x, $y))
+        AstNode::new(CodeLocation::default(), Span::default(), UnaryOp($x, $y))
     };
 }
 
@@ -52,7 +92,8 @@ macro_rules! un_ast_b {
 macro_rules! bin_ast {
     ($x:expr, $y:expr, $z:expr) => {
         AstNode::new(
-            CodeLocation::new(usize::MAX, usize::MAX),
+            CodeLocation::default(),
+            Span::default(),
             BinaryOp($x, $y, $z),
         )
     };
@@ -67,7 +108,8 @@ macro_rules! bin_ast_b {
 macro_rules! con_ast {
     ($x:expr, $y:expr, $z:expr) => {
         AstNode::new(
-            CodeLocation::new(usize::MAX, usize::MAX),
+            CodeLocation::default(),
+            Span::default(),
             Conditional($x, $y, $z),
         )
     };
@@ -81,7 +123,7 @@ macro_rules! con_ast_b {
 
 macro_rules! fun_ast {
     ($x:expr, $y:expr) => {
-        AstNode::new(CodeLocation::new(usize::MAX, usize::MAX), FunCall($x, $y))
+        AstNode::new(CodeLocation::default(), Span::default(), FunCall($x, $y))
     };
 }
 
@@ -93,7 +135,7 @@ macro_rules! fun_ast_b {
 
 macro_rules! block_ast {
     ($x:expr) => {
-        AstNode::new(CodeLocation::new(usize::MAX, usize::MAX), Block($x))
+        AstNode::new(CodeLocation::default(), Span::default(), Block($x))
     };
 }
 
@@ -105,14 +147,15 @@ macro_rules! block_ast_b {
 
 macro_rules! empty_ast {
     () => {
-        AstNode::new(CodeLocation::new(usize::MAX, usize::MAX), EmptyLiteral())
+        AstNode::new(CodeLocation::default(), Span::default(), EmptyLiteral())
     };
 }
 
 macro_rules! var_ast {
     ($x:expr, $y:expr, $z:expr) => {
         AstNode::new(
-            CodeLocation::new(usize::MAX, usize::MAX),
+            CodeLocation::default(),
+            Span::default(),
             VarDeclaration($x, $y, $z),
         )
     };
@@ -120,7 +163,7 @@ macro_rules! var_ast {
 
 macro_rules! while_ast {
     ($x:expr, $y:expr) => {
-        AstNode::new(CodeLocation::new(usize::MAX, usize::MAX), While($x, $y))
+        AstNode::new(CodeLocation::default(), Span::default(), While($x, $y))
     };
 }
 
@@ -130,12 +173,24 @@ macro_rules! while_ast_b {
     };
 }
 
+macro_rules! defer_ast {
+    ($x:expr) => {
+        AstNode::new(CodeLocation::default(), Span::default(), Defer($x))
+    };
+}
+
 #[test]
 #[should_panic]
 fn test_empty() {
     parse(&[]).unwrap();
 }
 
+#[test]
+fn test_empty_location() {
+    let result = parse(&[]).unwrap_err();
+    assert_eq!(result.errors[0].loc, CodeLocation::new(1, 1));
+}
+
 #[test]
 #[should_panic]
 fn test_invalid_start() {
@@ -156,22 +211,53 @@ fn test_invalid_end() {
 
 #[test]
 fn test_binary_op_basic() {
-    let result = parse(&tokenize("1 + 23").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 + 23").unwrap()).unwrap());
     assert_eq!(result, bin_ast!(int_ast_b!(1), "+", int_ast_b!(23)));
 
-    let result = parse(&tokenize("4 - 56").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("4 - 56").unwrap()).unwrap());
     assert_eq!(result, bin_ast!(int_ast_b!(4), "-", int_ast_b!(56)));
 
-    let result = parse(&tokenize("1 * 2").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 * 2").unwrap()).unwrap());
     assert_eq!(result, bin_ast!(int_ast_b!(1), "*", int_ast_b!(2)));
 
-    let result = parse(&tokenize("1 / 2").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 / 2").unwrap()).unwrap());
     assert_eq!(result, bin_ast!(int_ast_b!(1), "/", int_ast_b!(2)));
 }
 
+#[test]
+fn test_int_literals() {
+    let result = strip_locations(parse(&tokenize("0xFF").unwrap()).unwrap());
+    assert_eq!(result, int_ast!(255));
+
+    let result = strip_locations(parse(&tokenize("0b1010").unwrap()).unwrap());
+    assert_eq!(result, int_ast!(10));
+
+    let result = strip_locations(parse(&tokenize("0o17").unwrap()).unwrap());
+    assert_eq!(result, int_ast!(15));
+
+    let result = strip_locations(parse(&tokenize("1_000_000").unwrap()).unwrap());
+    assert_eq!(result, int_ast!(1_000_000));
+
+    let result = strip_locations(parse(&tokenize("0x_dead_BEEF").unwrap()).unwrap());
+    assert_eq!(result, int_ast!(0xdeadbeef));
+}
+
+#[test]
+#[should_panic]
+fn test_int_literal_no_digits() {
+    parse(&tokenize("0x_").unwrap()).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn test_int_literal_too_large() {
+    parse(&tokenize("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap()).unwrap();
+}
+
 #[test]
 fn test_binary_op_all_levels() {
-    let result = parse(&tokenize("1 * 2 + 3 < 4 == 5 and 6 or 7").unwrap()).unwrap();
+    let result =
+        strip_locations(parse(&tokenize("1 * 2 + 3 < 4 == 5 and 6 or 7").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -200,16 +286,16 @@ fn test_binary_op_all_levels() {
 
 #[test]
 fn test_binary_op_identifier() {
-    let result = parse(&tokenize("a + 1").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("a + 1").unwrap()).unwrap());
     assert_eq!(result, bin_ast!(id_ast_b!("a"), "+", int_ast_b!(1)));
 
-    let result = parse(&tokenize("1 - a").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 - a").unwrap()).unwrap());
     assert_eq!(result, bin_ast!(int_ast_b!(1), "-", id_ast_b!("a")));
 }
 
 #[test]
 fn test_binary_op_multiple() {
-    let result = parse(&tokenize("1 + 2 - 3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 + 2 - 3").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -222,7 +308,7 @@ fn test_binary_op_multiple() {
 
 #[test]
 fn test_binary_op_precedence() {
-    let result = parse(&tokenize("1 + 2 * 3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 + 2 * 3").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -232,7 +318,7 @@ fn test_binary_op_precedence() {
         )
     );
 
-    let result = parse(&tokenize("1 - 2 / 3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 - 2 / 3").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -245,7 +331,7 @@ fn test_binary_op_precedence() {
 
 #[test]
 fn test_assignment_basic() {
-    let result = parse(&tokenize("a = 1 + 2").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("a = 1 + 2").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -258,7 +344,7 @@ fn test_assignment_basic() {
 
 #[test]
 fn test_assignment_chain() {
-    let result = parse(&tokenize("a = b = 1 + 2").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("a = b = 1 + 2").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -281,16 +367,16 @@ fn test_assignment_invalid() {
 
 #[test]
 fn test_unary_basic() {
-    let result = parse(&tokenize("not x").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("not x").unwrap()).unwrap());
     assert_eq!(result, un_ast!("not", id_ast_b!("x")));
 
-    let result = parse(&tokenize("-x").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("-x").unwrap()).unwrap());
     assert_eq!(result, un_ast!("-", id_ast_b!("x")));
 
-    let result = parse(&tokenize("-1").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("-1").unwrap()).unwrap());
     assert_eq!(result, un_ast!("-", int_ast_b!(1)));
 
-    let result = parse(&tokenize("-1 + 2").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("-1 + 2").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(un_ast_b!("-", int_ast_b!(1)), "+", int_ast_b!(2))
@@ -299,16 +385,16 @@ fn test_unary_basic() {
 
 #[test]
 fn test_unary_chain() {
-    let result = parse(&tokenize("not not x").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("not not x").unwrap()).unwrap());
     assert_eq!(result, un_ast!("not", un_ast_b!("not", id_ast_b!("x"))));
 
-    let result = parse(&tokenize("--x").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("--x").unwrap()).unwrap());
     assert_eq!(result, un_ast!("-", un_ast_b!("-", id_ast_b!("x"))));
 
-    let result = parse(&tokenize("--1").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("--1").unwrap()).unwrap());
     assert_eq!(result, un_ast!("-", un_ast_b!("-", int_ast_b!(1))));
 
-    let result = parse(&tokenize("--1 + 2").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("--1 + 2").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -321,7 +407,7 @@ fn test_unary_chain() {
 
 #[test]
 fn test_parenthesized() {
-    let result = parse(&tokenize("(1+2)*3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("(1+2)*3").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -334,7 +420,7 @@ fn test_parenthesized() {
 
 #[test]
 fn test_parenthesized_nested() {
-    let result = parse(&tokenize("((1 - 2))/3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("((1 - 2))/3").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -344,7 +430,7 @@ fn test_parenthesized_nested() {
         )
     );
 
-    let result = parse(&tokenize("((1 + 2)*3) / 4").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("((1 + 2)*3) / 4").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -367,7 +453,7 @@ fn test_parenthesized_mismatched() {
 
 #[test]
 fn test_if_then() {
-    let result = parse(&tokenize("if 1 + 2 then 3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("if 1 + 2 then 3").unwrap()).unwrap());
     assert_eq!(
         result,
         con_ast!(
@@ -380,7 +466,7 @@ fn test_if_then() {
 
 #[test]
 fn test_if_then_else() {
-    let result = parse(&tokenize("if a then b + c else 1 * 2").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("if a then b + c else 1 * 2").unwrap()).unwrap());
     assert_eq!(
         result,
         con_ast!(
@@ -393,7 +479,7 @@ fn test_if_then_else() {
 
 #[test]
 fn test_if_then_else_embedded() {
-    let result = parse(&tokenize("1 + if true then 2 else 3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 + if true then 2 else 3").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -406,7 +492,9 @@ fn test_if_then_else_embedded() {
 
 #[test]
 fn test_if_then_else_nested() {
-    let result = parse(&tokenize("if true then if false then 1 else 2 else 3").unwrap()).unwrap();
+    let result = strip_locations(
+        parse(&tokenize("if true then if false then 1 else 2 else 3").unwrap()).unwrap(),
+    );
     assert_eq!(
         result,
         con_ast!(
@@ -425,10 +513,10 @@ fn test_if_no_then() {
 
 #[test]
 fn test_func_basic() {
-    let result = parse(&tokenize("f(a, b)").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("f(a, b)").unwrap()).unwrap());
     assert_eq!(result, fun_ast!("f", vec![id_ast!("a"), id_ast!("b"),]));
 
-    let result = parse(&tokenize("f(a, 1 + 2)").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("f(a, 1 + 2)").unwrap()).unwrap());
     assert_eq!(
         result,
         fun_ast!(
@@ -437,13 +525,13 @@ fn test_func_basic() {
         )
     );
 
-    let result = parse(&tokenize("f()").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("f()").unwrap()).unwrap());
     assert_eq!(result, fun_ast!("f", vec![]));
 }
 
 #[test]
 fn test_func_embedded() {
-    let result = parse(&tokenize("1 + f(a)").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 + f(a)").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(int_ast_b!(1), "+", fun_ast_b!("f", vec![id_ast!("a")]))
@@ -452,7 +540,7 @@ fn test_func_embedded() {
 
 #[test]
 fn test_func_nested() {
-    let result = parse(&tokenize("f(a, g(b))").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("f(a, g(b))").unwrap()).unwrap());
     assert_eq!(
         result,
         fun_ast!("f", vec![id_ast!("a"), fun_ast!("g", vec![id_ast!("b")]),])
@@ -473,7 +561,7 @@ fn test_func_missing_close() {
 
 #[test]
 fn test_block_basic() {
-    let result = parse(&tokenize("{ a = 1; b; }").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{ a = 1; b; }").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![
@@ -483,7 +571,7 @@ fn test_block_basic() {
         ])
     );
 
-    let result = parse(&tokenize("{ a = 1; b }").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{ a = 1; b }").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![
@@ -495,7 +583,7 @@ fn test_block_basic() {
 
 #[test]
 fn test_block_embedded() {
-    let result = parse(&tokenize("{ 1 + 2 } * 3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{ 1 + 2 } * 3").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -508,7 +596,7 @@ fn test_block_embedded() {
 
 #[test]
 fn test_block_nested() {
-    let result = parse(&tokenize("{ a = { 1 + 2}}").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{ a = { 1 + 2}}").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![bin_ast!(
@@ -525,6 +613,15 @@ fn test_block_unmatched() {
     parse(&tokenize("{ a = 1 ").unwrap()).unwrap();
 }
 
+#[test]
+fn test_block_unmatched_after_semicolon() {
+    let result = parse(&tokenize("{ a = 1;").unwrap()).unwrap_err();
+    assert_eq!(
+        result.to_string(),
+        "ParserError: Expected `}` but found end of input"
+    );
+}
+
 #[test]
 #[should_panic]
 fn test_block_missing_semicolon() {
@@ -533,10 +630,10 @@ fn test_block_missing_semicolon() {
 
 #[test]
 fn test_var_basic() {
-    let result = parse(&tokenize("var x = 1").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("var x = 1").unwrap()).unwrap());
     assert_eq!(result, var_ast!("x", int_ast_b!(1), None));
 
-    let result = parse(&tokenize("{ var x = 1; x = 2; }").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{ var x = 1; x = 2; }").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![
@@ -549,33 +646,54 @@ fn test_var_basic() {
 
 #[test]
 fn test_var_typed() {
-    let result = parse(&tokenize("var x: Int = 1").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("var x: Int = 1").unwrap()).unwrap());
     assert_eq!(
         result,
         var_ast!(
             "x",
             int_ast_b!(1),
-            Some(TypeExpression::Int(CodeLocation::new(
-                usize::MAX,
-                usize::MAX
-            )))
+            Some(TypeExpression::Int(
+                CodeLocation::default(),
+                Span::default()
+            ))
         )
     );
 
-    let result = parse(&tokenize("var x: Bool = true").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("var x: Bool = true").unwrap()).unwrap());
     assert_eq!(
         result,
         var_ast!(
             "x",
             bool_ast_b!(true),
-            Some(TypeExpression::Bool(CodeLocation::new(
-                usize::MAX,
-                usize::MAX
-            )))
+            Some(TypeExpression::Bool(
+                CodeLocation::default(),
+                Span::default()
+            ))
         )
     );
 }
 
+#[test]
+fn test_var_keyword_name() {
+    let result = parse(&tokenize("var if = 1").unwrap()).unwrap_err();
+    assert_eq!(
+        format!("{result}"),
+        "ParserError: Reserved keyword `if` can't be used as a variable name"
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_macro_keyword_name() {
+    parse(&tokenize("macro while(x) { x }").unwrap()).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn test_keyword_as_identifier() {
+    parse(&tokenize("1 + then").unwrap()).unwrap();
+}
+
 #[test]
 #[should_panic]
 fn test_var_chain() {
@@ -590,7 +708,7 @@ fn test_var_embedded() {
 
 #[test]
 fn test_omitting_semicolons() {
-    let result = parse(&tokenize("{ { a } { b } }").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{ { a } { b } }").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![
@@ -599,7 +717,7 @@ fn test_omitting_semicolons() {
         ])
     );
 
-    let result = parse(&tokenize("{ if true then { a } b }").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{ if true then { a } b }").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![
@@ -608,7 +726,7 @@ fn test_omitting_semicolons() {
         ])
     );
 
-    let result = parse(&tokenize("{ if true then { a }; b }").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{ if true then { a }; b }").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![
@@ -617,7 +735,8 @@ fn test_omitting_semicolons() {
         ])
     );
 
-    let result = parse(&tokenize("{ if true then { a } else { b } c }").unwrap()).unwrap();
+    let result =
+        strip_locations(parse(&tokenize("{ if true then { a } else { b } c }").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![
@@ -630,7 +749,7 @@ fn test_omitting_semicolons() {
         ])
     );
 
-    let result = parse(&tokenize("x = { { f(a) } { b } }").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("x = { { f(a) } { b } }").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -652,7 +771,7 @@ fn test_omitting_semicolons_invalid() {
 
 #[test]
 fn test_while_do() {
-    let result = parse(&tokenize("while 1 + 2 do 3").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("while 1 + 2 do 3").unwrap()).unwrap());
     assert_eq!(
         result,
         while_ast!(bin_ast_b!(int_ast_b!(1), "+", int_ast_b!(2)), int_ast_b!(3))
@@ -661,7 +780,7 @@ fn test_while_do() {
 
 #[test]
 fn test_while_do_embedded() {
-    let result = parse(&tokenize("1 + while true do 2").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("1 + while true do 2").unwrap()).unwrap());
     assert_eq!(
         result,
         bin_ast!(
@@ -674,7 +793,8 @@ fn test_while_do_embedded() {
 
 #[test]
 fn test_while_do_nested() {
-    let result = parse(&tokenize("while true do while false do 1").unwrap()).unwrap();
+    let result =
+        strip_locations(parse(&tokenize("while true do while false do 1").unwrap()).unwrap());
     assert_eq!(
         result,
         while_ast!(
@@ -690,15 +810,81 @@ fn test_while_no_do() {
     parse(&tokenize("while true").unwrap()).unwrap();
 }
 
+#[test]
+fn test_defer() {
+    let result = strip_locations(parse(&tokenize("{ defer f(a); b }").unwrap()).unwrap());
+    assert_eq!(
+        result,
+        block_ast!(vec![
+            defer_ast!(fun_ast_b!("f", vec![id_ast!("a")])),
+            id_ast!("b"),
+        ])
+    );
+
+    let result = strip_locations(parse(&tokenize("{ defer { a } b }").unwrap()).unwrap());
+    assert_eq!(
+        result,
+        block_ast!(vec![
+            defer_ast!(block_ast_b!(vec![id_ast!("a")])),
+            id_ast!("b"),
+        ])
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_defer_top_level() {
+    parse(&tokenize("defer f(a); b").unwrap()).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn test_defer_embedded() {
+    parse(&tokenize("{ if true then defer f(a) }").unwrap()).unwrap();
+}
+
+#[test]
+fn test_macro_definition() {
+    let result =
+        strip_locations(parse(&tokenize("macro double(x) { x + x }; double(1)").unwrap()).unwrap());
+    assert_eq!(
+        result,
+        block_ast!(vec![
+            AstNode::new(
+                CodeLocation::default(),
+                Span::default(),
+                MacroDefinition(
+                    "double",
+                    vec!["x"],
+                    block_ast_b!(vec![bin_ast!(id_ast_b!("x"), "+", id_ast_b!("x"))])
+                )
+            ),
+            fun_ast!("double", vec![int_ast!(1)]),
+        ])
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_macro_definition_embedded() {
+    parse(&tokenize("1 + macro double(x) { x + x }").unwrap()).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn test_macro_definition_no_block() {
+    parse(&tokenize("macro double(x) x + x").unwrap()).unwrap();
+}
+
 #[test]
 fn test_multiple_top_levels() {
-    let result = parse(&tokenize("a;").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("a;").unwrap()).unwrap());
     assert_eq!(result, block_ast!(vec![id_ast!("a"), empty_ast!()]));
 
-    let result = parse(&tokenize("a; b").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("a; b").unwrap()).unwrap());
     assert_eq!(result, block_ast!(vec![id_ast!("a"), id_ast!("b")]));
 
-    let result = parse(&tokenize("{}{}").unwrap()).unwrap();
+    let result = strip_locations(parse(&tokenize("{}{}").unwrap()).unwrap());
     assert_eq!(
         result,
         block_ast!(vec![block_ast!(vec![]), block_ast!(vec![])])
@@ -728,6 +914,7 @@ fn test_large() {
         .unwrap(),
     )
     .unwrap();
+    let result = strip_locations(result);
 
     assert_eq!(
         result,
@@ -760,3 +947,376 @@ fn test_large() {
         ])
     );
 }
+
+#[test]
+fn test_spans() {
+    let result = parse(&tokenize("1 + (2 * 3)").unwrap()).unwrap();
+    assert_eq!(result.span, Span::new(0, 11));
+
+    let BinaryOp(left, _, right) = result.expr else {
+        panic!("Expected a binary op!");
+    };
+    assert_eq!(left.span, Span::new(0, 1));
+    assert_eq!(right.span, Span::new(4, 11));
+
+    let result = parse(&tokenize("var x = f(a,\n  b); x").unwrap()).unwrap();
+    let Block(expressions) = result.expr else {
+        panic!("Expected a block!");
+    };
+    assert_eq!(expressions[0].span, Span::new(0, 17));
+    assert_eq!(expressions[0].loc, CodeLocation::new(1, 1));
+    assert_eq!(expressions[1].span, Span::new(19, 20));
+    assert_eq!(expressions[1].loc, CodeLocation::new(2, 7));
+}
+
+#[test]
+fn test_recovery_multiple_errors() {
+    let result = parse(&tokenize("var = 1; 1 + ; var y = 2; y").unwrap()).unwrap_err();
+    assert_eq!(
+        format!("{result}"),
+        "ParserError: Expected a name but found `=`\n\
+         ParserError: Unexpected `;`"
+    );
+
+    let (ast, errors) = parse_with_recovery(
+        &tokenize("var = 1; 1 + ; var y = 2; y").unwrap(),
+        DEFAULT_MAX_NESTING_DEPTH,
+    );
+    assert_eq!(errors.len(), 2);
+
+    let Block(expressions) = &ast.expr else {
+        panic!("Expected a block!");
+    };
+    assert!(matches!(expressions[0].expr, Error(_)));
+    assert_eq!(expressions[0].span, Span::new(0, 8));
+    assert!(matches!(expressions[1].expr, Error(_)));
+    assert_eq!(
+        strip_locations(expressions[2].clone()),
+        var_ast!("y", int_ast_b!(2), None)
+    );
+    assert_eq!(strip_locations(expressions[3].clone()), id_ast!("y"));
+}
+
+#[test]
+fn test_recovery_in_block() {
+    let (ast, errors) = parse_with_recovery(
+        &tokenize("{ a b; c }; if then 1; d").unwrap(),
+        DEFAULT_MAX_NESTING_DEPTH,
+    );
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[0].to_string(),
+        "ParserError: Expected `;` but found `b`"
+    );
+    assert_eq!(errors[1].to_string(), "ParserError: Unexpected `then`");
+
+    let Block(expressions) = &ast.expr else {
+        panic!("Expected a block!");
+    };
+    let Block(block_expressions) = &expressions[0].expr else {
+        panic!("Expected a block!");
+    };
+    assert_eq!(block_expressions.len(), 3);
+    assert!(matches!(block_expressions[1].expr, Error(_)));
+    assert_eq!(strip_locations(block_expressions[2].clone()), id_ast!("c"));
+    assert_eq!(strip_locations(expressions[2].clone()), id_ast!("d"));
+}
+
+#[test]
+fn test_recovery_at_keyword() {
+    let (ast, errors) = parse_with_recovery(
+        &tokenize("1 + + var x = 2; x").unwrap(),
+        DEFAULT_MAX_NESTING_DEPTH,
+    );
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        strip_locations(ast),
+        block_ast!(vec![
+            AstNode::new(
+                CodeLocation::default(),
+                Span::default(),
+                Error(String::from("Unexpected `+`"))
+            ),
+            var_ast!("x", int_ast_b!(2), None),
+            id_ast!("x"),
+        ])
+    );
+}
+
+#[test]
+fn test_recovery_unclosed_block() {
+    let (_, errors) = parse_with_recovery(&tokenize("{ a b").unwrap(), DEFAULT_MAX_NESTING_DEPTH);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[1].to_string(),
+        "ParserError: Expected `}` but found end of input"
+    );
+
+    let (_, errors) = parse_with_recovery(&tokenize("} a; }").unwrap(), DEFAULT_MAX_NESTING_DEPTH);
+    assert_eq!(errors.len(), 2);
+}
+
+#[test]
+fn test_error_locations() {
+    let (_, errors) = parse_with_recovery(
+        &tokenize(
+            "var x = 1 2;
+{ if then }",
+        )
+        .unwrap(),
+        DEFAULT_MAX_NESTING_DEPTH,
+    );
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].loc, CodeLocation::new(1, 11));
+    assert_eq!(errors[0].span, Span::new(10, 11));
+    assert_eq!(errors[1].loc, CodeLocation::new(2, 6));
+    assert_eq!(errors[1].span, Span::new(18, 22));
+}
+
+#[test]
+fn test_keyword_suggestions() {
+    let suggestions = |code| {
+        let (_, errors) = parse_with_recovery(&tokenize(code).unwrap(), DEFAULT_MAX_NESTING_DEPTH);
+        errors
+            .into_iter()
+            .map(|error| error.notes)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        suggestions("if a thne b"),
+        vec![vec![String::from("Did you mean then?")]]
+    );
+    assert_eq!(
+        suggestions("whiel a do b"),
+        vec![vec![String::from("Did you mean while?")]]
+    );
+    assert_eq!(
+        suggestions("if a then b esle c"),
+        vec![vec![String::from("Did you mean else?")]]
+    );
+    assert_eq!(suggestions("foo qux"), vec![Vec::<String>::new()]);
+    assert_eq!(suggestions("fun f(x: Int)"), vec![Vec::<String>::new()]);
+    assert_eq!(suggestions("x = 1 f y"), vec![Vec::<String>::new()]);
+}
+
+// Deeply nested inputs need the stack size the compiler normally runs with
+fn with_compiler_stack(f: impl FnOnce() + Send + 'static) {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_nesting_limit() {
+    let tokens = tokenize("((1))").unwrap();
+    assert!(parse_with_max_depth(&tokens, 3).is_ok());
+
+    let result = parse_with_max_depth(&tokens, 2).unwrap_err();
+    assert_eq!(
+        format!("{result}"),
+        "ParserError: Expressions nested deeper than the maximum of 2 levels"
+    );
+
+    let tokens = tokenize("{ a; { b; { c } } }").unwrap();
+    assert!(parse_with_max_depth(&tokens, 4).is_ok());
+    assert!(parse_with_max_depth(&tokens, 3).is_err());
+
+    let tokens = tokenize("- - not 1").unwrap();
+    assert!(parse_with_max_depth(&tokens, 4).is_ok());
+    assert!(parse_with_max_depth(&tokens, 3).is_err());
+
+    // Only operands binding more tightly nest deeper, however long the chain of operators
+    let tokens = tokenize("1 + 2 * 3 * 4 + 5").unwrap();
+    assert!(parse_with_max_depth(&tokens, 3).is_ok());
+    assert!(parse_with_max_depth(&tokens, 2).is_err());
+
+    let code = format!("1{}", " + 1".repeat(299));
+    let tokens = tokenize(&code).unwrap();
+    assert!(parse_with_max_depth(&tokens, 2).is_ok());
+}
+
+#[test]
+fn test_nesting_pathological() {
+    with_compiler_stack(|| {
+        let depth = 10_000;
+        let programs = [
+            format!("{}1{}", "(".repeat(depth), ")".repeat(depth)),
+            format!("{}a{}", "{".repeat(depth), "}".repeat(depth)),
+            format!("{}1", "- ".repeat(depth)),
+            format!("{}1", "a = ".repeat(depth)),
+            format!("{}1", "if true then ".repeat(depth)),
+            format!("{}1", "(".repeat(depth)),
+        ];
+
+        for code in programs {
+            let tokens = tokenize(&code).unwrap();
+            let (ast, errors) = parse_with_recovery(&tokens, DEFAULT_MAX_NESTING_DEPTH);
+
+            assert!(matches!(ast.expr, Error(_)));
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0]
+                .to_string()
+                .starts_with("ParserError: Expressions nested deeper than the maximum of 256"));
+        }
+
+        // Chains of operators deepen the tree without nesting
+        let code = format!("1{}", " + 1".repeat(depth));
+        let tokens = tokenize(&code).unwrap();
+        let (ast, errors) = parse_with_recovery(&tokens, DEFAULT_MAX_NESTING_DEPTH);
+
+        assert!(matches!(ast.expr, Error(_)));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "ParserError: Expression nested deeper than the maximum of 2048 levels"
+        );
+        assert_eq!(errors[0].loc, CodeLocation::new(1, 4 * MAX_TREE_DEPTH - 1));
+    });
+}
+
+#[test]
+fn test_nesting_at_limit_compiles() {
+    use crate::compiler::{
+        interpreter::interpret,
+        ir_generator::generate_ir,
+        macro_expander::{expand_macros, FreshNames},
+        symtab::SymTab,
+        type_checker::type_check,
+    };
+
+    with_compiler_stack(|| {
+        let depth = DEFAULT_MAX_NESTING_DEPTH - 1;
+        let chain = |terms: usize| format!("1{}", " + 1".repeat(terms - 1));
+        let programs = [
+            format!("{}1{}", "(".repeat(depth), ")".repeat(depth)),
+            format!("{}1{}", "{".repeat(depth), "}".repeat(depth)),
+            format!("{}1", "- ".repeat(depth)),
+            chain(MAX_TREE_DEPTH),
+            format!(
+                "{}{}{}",
+                "{".repeat(depth - 1),
+                chain(MAX_TREE_DEPTH - depth + 1),
+                "}".repeat(depth - 1)
+            ),
+            format!(
+                "{}1{}",
+                "if true then ".repeat(depth),
+                " else 2".repeat(depth)
+            ),
+        ];
+
+        for code in programs {
+            let names = FreshNames::default();
+            let tokens = tokenize(&code).unwrap();
+            let mut ast = parse(&tokens).unwrap();
+            expand_macros(&mut ast, &names).unwrap();
+            type_check(&mut ast, &mut SymTab::new_type_table()).unwrap();
+            interpret(&ast, &mut SymTab::new_val_table()).unwrap();
+            generate_ir(&ast, false).unwrap();
+        }
+    });
+}
+
+#[test]
+fn test_operator_declaration() {
+    let result =
+        strip_locations(parse(&tokenize("infixl 7 <+> = f; 1 + 2 <+> 3 * 4").unwrap()).unwrap());
+    assert_eq!(
+        result,
+        block_ast!(vec![
+            empty_ast!(),
+            bin_ast!(
+                int_ast_b!(1),
+                "+",
+                bin_ast_b!(
+                    fun_ast_b!("f", vec![int_ast!(2), int_ast!(3)]),
+                    "*",
+                    int_ast_b!(4)
+                )
+            ),
+        ])
+    );
+
+    let result =
+        strip_locations(parse(&tokenize("infixl 1 <+> = f; 1 <+> 2 <+> 3 == 4").unwrap()).unwrap());
+    assert_eq!(
+        result,
+        block_ast!(vec![
+            empty_ast!(),
+            fun_ast!(
+                "f",
+                vec![
+                    fun_ast!("f", vec![int_ast!(1), int_ast!(2)]),
+                    bin_ast!(int_ast_b!(3), "==", int_ast_b!(4))
+                ]
+            ),
+        ])
+    );
+
+    // Declarations apply to the rest of the program, not just the block they are in
+    let result =
+        strip_locations(parse(&tokenize("{ infixl 7 <+> = f }; 1 <+> 2").unwrap()).unwrap());
+    assert_eq!(
+        result,
+        block_ast!(vec![
+            block_ast!(vec![empty_ast!()]),
+            fun_ast!("f", vec![int_ast!(1), int_ast!(2)]),
+        ])
+    );
+}
+
+#[test]
+fn test_operator_declaration_associativity() {
+    let result =
+        strip_locations(parse(&tokenize("infixr 8 ^^ = pow; a ^^ b ^^ c").unwrap()).unwrap());
+    assert_eq!(
+        result,
+        block_ast!(vec![
+            empty_ast!(),
+            fun_ast!(
+                "pow",
+                vec![
+                    id_ast!("a"),
+                    fun_ast!("pow", vec![id_ast!("b"), id_ast!("c")])
+                ]
+            ),
+        ])
+    );
+
+    let result = parse(&tokenize("infix 4 <=> = cmp; a <=> b").unwrap());
+    assert!(result.is_ok());
+
+    let result = parse(&tokenize("infix 4 <=> = cmp; a <=> b <=> c").unwrap()).unwrap_err();
+    assert_eq!(
+        format!("{result}"),
+        "ParserError: Non-associative operator `<=>` can't be chained with `<=>`"
+    );
+}
+
+#[test]
+fn test_operator_declaration_invalid() {
+    let result = parse(&tokenize("infixl 5 + = f").unwrap()).unwrap_err();
+    assert_eq!(
+        format!("{result}"),
+        "ParserError: Operator `+` is built in and can't be redeclared"
+    );
+
+    let result = parse(&tokenize("infixl 5 <+> = f; infixr 6 <+> = g").unwrap()).unwrap_err();
+    assert_eq!(
+        format!("{result}"),
+        "ParserError: Operator `<+>` is already declared and can't be redeclared"
+    );
+
+    let result = parse(&tokenize("infixl 10 <+> = f").unwrap()).unwrap_err();
+    assert_eq!(
+        format!("{result}"),
+        "ParserError: Operator precedence must be between 1 and 9 but found `10`"
+    );
+
+    assert!(parse(&tokenize("infixl 5 <+> = if").unwrap()).is_err());
+    assert!(parse(&tokenize("1 + infixl 5 <+> = f").unwrap()).is_err());
+}
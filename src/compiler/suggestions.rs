@@ -0,0 +1,103 @@
+// Names within this many edits of a misspelt name are suggested, scaled by the name's length so
+// short names don't match everything
+fn max_distance(name: &str) -> usize {
+    (name.chars().count() / 3).max(1)
+}
+
+// Optimal string alignment distance: insertions, deletions, substitutions and swaps of adjacent
+// characters all count as one edit
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+
+    // Three rows of the distance matrix are enough, the current and the two before it
+    let mut two_back = vec![0; b.len() + 1];
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(two_back[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut two_back, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+// Up to the given number of candidates close enough to the name, closest first
+pub fn closest_names<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+    count: usize,
+) -> Vec<&'a str> {
+    let mut close = candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance(name))
+        .collect::<Vec<_>>();
+    close.sort();
+    close.dedup();
+
+    close
+        .into_iter()
+        .take(count)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+pub fn did_you_mean(names: &[&str]) -> Option<String> {
+    match names {
+        [] => None,
+        [name] => Some(format!("Did you mean {}?", name)),
+        [names @ .., last] => Some(format!("Did you mean {} or {}?", names.join(", "), last)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("print", "print"), 0);
+        assert_eq!(edit_distance("pritn", "print"), 1);
+        assert_eq!(edit_distance("prnt", "print"), 1);
+        assert_eq!(edit_distance("whiel", "while"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("ä", "a"), 1);
+    }
+
+    #[test]
+    fn test_closest_names() {
+        let candidates = ["print", "print_int", "print_bool", "read_int", "assert"];
+        assert_eq!(closest_names("pritn", candidates, 3), vec!["print"]);
+        assert_eq!(closest_names("print_it", candidates, 3), vec!["print_int"]);
+        assert_eq!(closest_names("x", candidates, 3), Vec::<&str>::new());
+
+        let candidates = ["ab", "ac", "ad", "ae", "ab"];
+        assert_eq!(closest_names("aa", candidates, 3), vec!["ab", "ac", "ad"]);
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        assert_eq!(did_you_mean(&[]), None);
+        assert_eq!(did_you_mean(&["a"]).unwrap(), "Did you mean a?");
+        assert_eq!(
+            did_you_mean(&["a", "b", "c"]).unwrap(),
+            "Did you mean a, b or c?"
+        );
+    }
+}
@@ -1,9 +1,18 @@
-use crate::compiler::variable::{Type, Value};
+use crate::compiler::{
+    diagnostic::Diagnostic,
+    error_codes::ErrorCode,
+    suggestions::{closest_names, did_you_mean},
+    token::{CodeLocation, Span},
+    variable::{Type, Value},
+};
 use std::{collections::HashMap, error::Error, fmt::Display};
 
 #[derive(Debug)]
 pub struct SymbolTableError {
+    code: ErrorCode,
     msg: String,
+    // Similarly named symbols, when the symbol wasn't found
+    suggestions: Vec<String>,
 }
 
 impl Display for SymbolTableError {
@@ -14,12 +23,42 @@ impl Display for SymbolTableError {
 
 impl Error for SymbolTableError {}
 
+impl SymbolTableError {
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    // The symbol table doesn't know where a symbol is used, so the caller provides the location
+    pub fn located(self, loc: CodeLocation, span: Span) -> Diagnostic {
+        let suggestions = self
+            .suggestions
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let diagnostic = Diagnostic::error(self.msg, loc, span).with_code(self.code);
+
+        match did_you_mean(&suggestions) {
+            Some(note) => diagnostic.with_note(note),
+            None => diagnostic,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct SymTab<'source, T> {
     tables: Vec<HashMap<&'source str, T>>,
 }
 
 impl<'source, T> SymTab<'source, T> {
+    // Similar names are only looked for when the error is reported, so lookups that are expected
+    // to miss use this instead
+    pub fn try_get(&mut self, symbol: &str) -> Option<&mut T> {
+        self.tables
+            .iter_mut()
+            .rev()
+            .find_map(|table| table.get_mut(symbol))
+    }
+
     pub fn get(&mut self, symbol: &str) -> Result<&mut T, SymbolTableError> {
         for i in (0..self.tables.len()).rev() {
             if self.tables[i].contains_key(symbol) {
@@ -27,7 +66,22 @@ impl<'source, T> SymTab<'source, T> {
             }
         }
         Err(SymbolTableError {
+            code: ErrorCode::UnknownSymbol,
             msg: format!("No symbol {} found!", symbol),
+            suggestions: closest_names(symbol, self.names(), 3)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        })
+    }
+
+    // Names that can be written in the source, leaving out operators, overload implementations
+    // and names generated by macro expansion
+    fn names(&self) -> impl Iterator<Item = &'source str> + '_ {
+        self.tables.iter().flat_map(|table| {
+            table.keys().copied().filter(|name| {
+                name.chars().all(|c| c.is_alphanumeric() || c == '_') && !name.starts_with("unary_")
+            })
         })
     }
 
@@ -48,7 +102,9 @@ impl<'source, T> SymTab<'source, T> {
             .is_some()
         {
             Err(SymbolTableError {
+                code: ErrorCode::AlreadyDefined,
                 msg: format!("Variable {} already defined in this scope!", name),
+                suggestions: Vec::new(),
             })
         } else {
             Ok(())
@@ -70,7 +126,18 @@ impl<'source> SymTab<'source, Type> {
         let globals = HashMap::from([
             ("print_bool", Func(vec![Bool], Box::new(Unit))),
             ("print_int", Func(vec![Int], Box::new(Unit))),
+            (
+                "print",
+                Overloaded(vec![
+                    ("print_int", Func(vec![Int], Box::new(Unit))),
+                    ("print_bool", Func(vec![Bool], Box::new(Unit))),
+                ]),
+            ),
             ("read_int", Func(vec![], Box::new(Int))),
+            ("assert", Func(vec![Bool], Box::new(Unit))),
+            ("exit", Func(vec![Int], Box::new(Unit))),
+            ("arg_count", Func(vec![], Box::new(Int))),
+            ("arg_int", Func(vec![Int], Box::new(Int))),
             ("+", Func(vec![Int, Int], Box::new(Int))),
             ("*", Func(vec![Int, Int], Box::new(Int))),
             ("-", Func(vec![Int, Int], Box::new(Int))),
@@ -80,6 +147,20 @@ impl<'source> SymTab<'source, Type> {
             ("<=", Func(vec![Int, Int], Box::new(Bool))),
             (">", Func(vec![Int, Int], Box::new(Bool))),
             (">=", Func(vec![Int, Int], Box::new(Bool))),
+            (
+                "==",
+                Overloaded(vec![
+                    ("==_int", Func(vec![Int, Int], Box::new(Bool))),
+                    ("==_bool", Func(vec![Bool, Bool], Box::new(Bool))),
+                ]),
+            ),
+            (
+                "!=",
+                Overloaded(vec![
+                    ("!=_int", Func(vec![Int, Int], Box::new(Bool))),
+                    ("!=_bool", Func(vec![Bool, Bool], Box::new(Bool))),
+                ]),
+            ),
             ("unary_not", Func(vec![Bool], Box::new(Bool))),
             ("unary_-", Func(vec![Int], Box::new(Int))),
             ("or", Func(vec![Bool, Bool], Box::new(Bool))),
@@ -109,6 +190,13 @@ impl<'source> SymTab<'source, Value> {
             (">=", Func(Value::ge)),
             ("unary_not", Func(Value::not)),
             ("unary_-", Func(Value::neg)),
+            ("print_int", Func(Value::print)),
+            ("print_bool", Func(Value::print)),
+            ("print", Func(Value::print)),
+            ("assert", Func(Value::assert)),
+            ("exit", Func(Value::exit)),
+            ("arg_count", Func(Value::arg_count)),
+            ("arg_int", Func(Value::arg_int)),
         ]);
 
         SymTab {
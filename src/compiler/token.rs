@@ -1,7 +1,6 @@
 use std::fmt;
 
-#[allow(clippy::derived_hash_with_manual_eq)]
-#[derive(Debug, Copy, Clone, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
 pub struct CodeLocation {
     line: usize,
     char: usize,
@@ -11,6 +10,14 @@ impl CodeLocation {
     pub fn new(line: usize, char: usize) -> Self {
         Self { line, char }
     }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn char(&self) -> usize {
+        self.char
+    }
 }
 
 impl fmt::Display for CodeLocation {
@@ -19,25 +26,35 @@ impl fmt::Display for CodeLocation {
     }
 }
 
-impl PartialEq for CodeLocation {
-    fn eq(&self, other: &Self) -> bool {
-        let true_match = self.line == other.line && self.char == other.char;
+// Byte offsets into the source, end exclusive
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
 
-        // For testing purposes
-        let simulated_match = self.line == usize::MAX
-            || self.char == usize::MAX
-            || other.line == usize::MAX
-            || other.char == usize::MAX;
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
 
-        true_match || simulated_match
+    // Smallest span covering both spans
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
     }
 }
 
+pub const KEYWORDS: [&str; 16] = [
+    "if", "then", "else", "while", "do", "var", "true", "false", "not", "and", "or", "defer",
+    "macro", "infixl", "infixr", "infix",
+];
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenType {
     Comment,
     Integer,
     Identifier,
+    Keyword,
     Operator,
     Punctuation,
     Whitespace,
@@ -49,6 +66,20 @@ impl TokenType {
         use TokenType::*;
         matches!(self, Whitespace | Comment)
     }
+
+    // How a token of the type is referred to in error messages
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TokenType::Comment => "a comment",
+            TokenType::Integer => "an integer",
+            TokenType::Identifier => "a name",
+            TokenType::Keyword => "a keyword",
+            TokenType::Operator => "an operator",
+            TokenType::Punctuation => "punctuation",
+            TokenType::Whitespace => "whitespace",
+            TokenType::End => "end of input",
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -56,14 +87,27 @@ pub struct Token<'source> {
     pub text: &'source str,
     pub token_type: TokenType,
     pub loc: CodeLocation,
+    pub span: Span,
+    // Whitespace and comments before the token, only kept when tokenizing losslessly
+    pub leading_trivia: &'source str,
 }
 
 impl<'source> Token<'source> {
-    pub fn new(text: &'source str, token_type: TokenType, loc: CodeLocation) -> Self {
+    pub fn new(text: &'source str, token_type: TokenType, loc: CodeLocation, span: Span) -> Self {
         Self {
             text,
             token_type,
             loc,
+            span,
+            leading_trivia: "",
+        }
+    }
+
+    // How the token is referred to in error messages, its debug form is only for the CST outline
+    pub fn describe(&self) -> String {
+        match self.token_type {
+            TokenType::End => String::from(TokenType::End.describe()),
+            _ => format!("`{}`", self.text),
         }
     }
 }
@@ -1,11 +1,19 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, sync::OnceLock};
 
-use crate::compiler::token::{CodeLocation, Token, TokenType};
+use crate::compiler::{
+    diagnostic::Diagnostic,
+    error_codes::ErrorCode,
+    parser::operators::CUSTOM_PRECEDENCES,
+    token::{CodeLocation, Span, Token, TokenType, KEYWORDS},
+};
 use regex::Regex;
 
 #[derive(Debug)]
 pub struct TokenizerError {
+    code: ErrorCode,
     msg: String,
+    loc: CodeLocation,
+    span: Span,
 }
 
 impl Display for TokenizerError {
@@ -16,83 +24,349 @@ impl Display for TokenizerError {
 
 impl Error for TokenizerError {}
 
-pub fn tokenize(code: &str) -> Result<Vec<Token>, TokenizerError> {
-    // We only want to compile the regexes once
-    // The ordering of these is important!
-    let regexes = vec![
-        (TokenType::Comment, Regex::new(r"^(//|#).*").unwrap()),
-        (TokenType::Whitespace, Regex::new(r"^[\s\t\n]+").unwrap()),
-        (
-            TokenType::Operator,
-            Regex::new(r"^(==|!=|<=|>=|=|<|>|\+|-|\*|/|\%)").unwrap(),
-        ),
-        (TokenType::Punctuation, Regex::new(r"^[\(\){},;:]").unwrap()),
-        (TokenType::Integer, Regex::new(r"^[0-9]+").unwrap()),
-        (
-            TokenType::Identifier,
-            Regex::new(r"^[[:alpha:]_][[:alpha:]0-9_]*").unwrap(),
-        ),
-    ];
+impl From<TokenizerError> for Diagnostic {
+    fn from(error: TokenizerError) -> Self {
+        Diagnostic::error(error.msg, error.loc, error.span).with_code(error.code)
+    }
+}
 
+pub fn tokenize(code: &str) -> Result<Vec<Token<'_>>, TokenizerError> {
+    Ok(tokenize_with_trivia(code, false)?.0)
+}
+
+// Only the comments, as tokens of their own, for reading directives like `// allow(lint)`
+pub fn tokenize_comments(code: &str) -> Result<Vec<Token<'_>>, TokenizerError> {
+    Ok(tokenize_with_trivia(code, false)?.2)
+}
+
+// Lossless mode: every token keeps the whitespace and comments before it, and whatever follows
+// the last token is returned separately, so the exact input can be regenerated from the tokens
+pub fn tokenize_lossless(code: &str) -> Result<(Vec<Token<'_>>, &str), TokenizerError> {
+    let (tokens, trailing_trivia, _) = tokenize_with_trivia(code, true)?;
+    Ok((tokens, trailing_trivia))
+}
+
+fn tokenize_with_trivia(
+    code: &str,
+    lossless: bool,
+) -> Result<(Vec<Token<'_>>, &str, Vec<Token<'_>>), TokenizerError> {
     let mut tokens = Vec::new();
+    let mut comments = Vec::new();
 
-    for (line_number, line) in code.lines().enumerate() {
-        let mut pos = 0;
+    // Tokens can span lines, so the whole buffer is processed at once
+    let mut pos = 0;
+    let mut line_number = 1; // 1-indexing
+    let mut column = 1; // Counted in characters, not bytes
+    let mut trivia_start = 0;
+    let mut custom_operators: Vec<&str> = Vec::new();
 
-        while pos < line.len() {
-            let mut valid_token = false;
-
-            for (token_type, regex_matcher) in &regexes {
-                let found_match = regex_matcher.find(&line[pos..]);
-
-                if let Some(token) = found_match {
-                    if !token_type.ignore() {
-                        let start = pos + token.start();
-                        let end = pos + token.end();
-                        tokens.push(Token::new(
-                            &line[start..end],
-                            *token_type,
-                            CodeLocation::new(line_number + 1, start + 1), // 1-indexing
-                        ));
-                    }
-
-                    valid_token = true;
-                    pos += token.end();
-                    break;
-                }
+    while pos < code.len() {
+        let loc = CodeLocation::new(line_number, column);
+
+        let scanned = if declares_operator(&tokens) {
+            custom_operator_len(&code[pos..]).map(|len| (TokenType::Operator, len))
+        } else {
+            None
+        };
+        let scanned = scanned.or_else(|| {
+            let builtin = scan_token(&code[pos..]);
+            let builtin_operator_len = match builtin {
+                Some((TokenType::Operator, len)) => len,
+                _ => 0,
+            };
+
+            // The longest operator wins, so declared operators can extend built in ones
+            custom_operators
+                .iter()
+                .filter(|operator| code[pos..].starts_with(*operator))
+                .map(|operator| operator.len())
+                .max()
+                .filter(|len| *len > builtin_operator_len)
+                .map(|len| (TokenType::Operator, len))
+                .or(builtin)
+        });
+
+        let Some((token_type, token_len)) = scanned else {
+            let first_char = code[pos..].chars().next().unwrap_or_default();
+            let (code, msg, len) = if code[pos..].starts_with("/*") {
+                let msg = String::from("Unterminated comment");
+                (ErrorCode::UnterminatedComment, msg, 2)
+            } else {
+                let msg = format!("Invalid token starting with '{}'", first_char);
+                (ErrorCode::InvalidToken, msg, first_char.len_utf8())
+            };
+            let span = Span::new(pos, pos + len);
+            return Err(TokenizerError {
+                code,
+                msg,
+                loc,
+                span,
+            });
+        };
+
+        if token_type == TokenType::Integer {
+            check_integer(&code[pos..], token_len).map_err(|(msg, len)| TokenizerError {
+                code: ErrorCode::InvalidIntegerLiteral,
+                msg,
+                loc,
+                span: Span::new(pos, pos + len),
+            })?;
+        }
+
+        let text = &code[pos..pos + token_len];
+        if !token_type.ignore() {
+            let token_type = if token_type == TokenType::Identifier && KEYWORDS.contains(&text) {
+                TokenType::Keyword
+            } else {
+                token_type
+            };
+            let mut token = Token::new(text, token_type, loc, Span::new(pos, pos + token_len));
+            if lossless {
+                token.leading_trivia = &code[trivia_start..pos];
             }
+            tokens.push(token);
+            trivia_start = pos + token_len;
 
-            if !valid_token {
-                return Err(TokenizerError {
-                    msg: format!(
-                        "Invalid token starting with '{}' on line {} in position {}",
-                        &line[pos..pos + 1],
-                        line_number + 1,
-                        pos + 1
-                    ),
-                });
+            // Operators are recognized once their declaration is complete, for the rest of the
+            // program like in the parser. Declarations the parser rejects are left out.
+            if let Some(operator) = declared_operator(&tokens) {
+                custom_operators.push(operator);
             }
+        } else if token_type == TokenType::Comment {
+            comments.push(Token::new(
+                text,
+                token_type,
+                loc,
+                Span::new(pos, pos + token_len),
+            ));
+        }
+
+        for c in text.chars() {
+            if c == '\n' {
+                line_number += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        pos += token_len;
+    }
+
+    let trailing_trivia = if lossless { &code[trivia_start..] } else { "" };
+    Ok((tokens, trailing_trivia, comments))
+}
+
+// Type and length in bytes of the token at the start of the code, decided by its first character
+fn scan_token(code: &str) -> Option<(TokenType, usize)> {
+    use TokenType::*;
+
+    let bytes = code.as_bytes();
+    let first = code.chars().next()?;
+    let second = bytes.get(1).copied().unwrap_or_default();
+
+    let scanned = match first {
+        '/' if second == b'*' => (Comment, block_comment_len(code)?),
+        '#' => (Comment, line_comment_len(code)),
+        '/' if second == b'/' => (Comment, line_comment_len(code)),
+        '=' | '!' | '<' | '>' if second == b'=' => (Operator, 2),
+        '=' | '<' | '>' | '+' | '-' | '*' | '/' | '%' => (Operator, 1),
+        '(' | ')' | '{' | '}' | ',' | ';' | ':' => (Punctuation, 1),
+        '0'..='9' => (Integer, integer_len(bytes)),
+        c if c.is_whitespace() => (Whitespace, whitespace_len(code)),
+        c if c == '_' || c.is_ascii_alphabetic() || !c.is_ascii() => {
+            (Identifier, identifier_len(code)?)
         }
+        _ => return None,
+    };
+
+    Some(scanned)
+}
+
+// Declarations look like `infixl 5 <+> = my_add`, with the operator following the precedence
+fn declares_operator(tokens: &[Token]) -> bool {
+    matches!(
+        tokens,
+        [.., keyword, precedence]
+            if matches!(keyword.text, "infixl" | "infixr" | "infix")
+                && precedence.token_type == TokenType::Integer
+    )
+}
+
+// The operator of a valid declaration ending the tokens. Redeclaring an operator is an error in
+// the parser, but recognizing it again changes nothing.
+fn declared_operator<'source>(tokens: &[Token<'source>]) -> Option<&'source str> {
+    match tokens {
+        [.., precedence, operator, equals, function]
+            if declares_operator(&tokens[..tokens.len() - 3])
+                && precedence
+                    .text
+                    .parse()
+                    .is_ok_and(|precedence| CUSTOM_PRECEDENCES.contains(&precedence))
+                && operator.token_type == TokenType::Operator
+                && equals.text == "="
+                && function.token_type == TokenType::Identifier =>
+        {
+            Some(operator.text)
+        }
+        _ => None,
     }
+}
+
+// Declared operators are any run of operator characters, stopping before a comment
+fn custom_operator_len(code: &str) -> Option<usize> {
+    const OPERATOR_CHARS: &[u8] = b"+-*/%<>=!&|^~?@$.";
+
+    let bytes = code.as_bytes();
+    let len = (0..bytes.len())
+        .take_while(|&i| {
+            OPERATOR_CHARS.contains(&bytes[i])
+                && !(bytes[i] == b'/' && matches!(bytes.get(i + 1), Some(b'/' | b'*')))
+        })
+        .count();
 
-    Ok(tokens)
+    (len > 0).then_some(len)
+}
+
+// Line comments run up to, but not including, the end of the line
+fn line_comment_len(code: &str) -> usize {
+    code.find('\n').unwrap_or(code.len())
+}
+
+fn whitespace_len(code: &str) -> usize {
+    code.find(|c: char| !c.is_whitespace())
+        .unwrap_or(code.len())
+}
+
+// A radix prefix without any digits after it is just the integer 0, which is then rejected by
+// check_integer
+fn integer_len(bytes: &[u8]) -> usize {
+    let digits_len = |start: usize, is_digit: fn(&u8) -> bool| {
+        bytes[start..]
+            .iter()
+            .take_while(|&b| *b == b'_' || is_digit(b))
+            .count()
+    };
+
+    let radix_digits = match bytes.get(..2) {
+        Some(b"0x") => digits_len(2, u8::is_ascii_hexdigit),
+        Some(b"0b") => digits_len(2, |b| matches!(b, b'0' | b'1')),
+        Some(b"0o") => digits_len(2, |b| matches!(b, b'0'..=b'7')),
+        _ => 0,
+    };
+
+    if radix_digits > 0 {
+        2 + radix_digits
+    } else {
+        1 + digits_len(1, u8::is_ascii_digit)
+    }
+}
+
+// Radix prefixes need a digit after them, and underscores can only go between digits or right
+// after the prefix. Errors cover the letters and digits run together with the literal.
+fn check_integer(code: &str, len: usize) -> Result<(), (String, usize)> {
+    let literal_len = code
+        .bytes()
+        .take_while(|b| *b == b'_' || b.is_ascii_alphanumeric())
+        .count();
+    let literal = &code[..literal_len];
+
+    let prefix = ["0x", "0b", "0o"]
+        .into_iter()
+        .find(|prefix| code.starts_with(prefix));
+    let digits = match prefix {
+        Some(_) => code.get(2..len).unwrap_or_default(),
+        None => &code[..len],
+    };
+
+    let msg = match prefix {
+        Some(prefix) if digits.bytes().all(|b| b == b'_') => format!(
+            "Integer literal {} has no digits after its {} prefix",
+            literal, prefix
+        ),
+        _ if digits.ends_with('_') || digits.contains("__") => format!(
+            "Integer literal {} can't end in an underscore or have two in a row",
+            literal
+        ),
+        _ => return Ok(()),
+    };
+    Err((msg, literal_len))
+}
+
+// Identifiers are nearly always ASCII, so the Unicode rules are only checked when needed
+fn identifier_len(code: &str) -> Option<usize> {
+    let ascii_len = code
+        .bytes()
+        .take_while(|b| *b == b'_' || b.is_ascii_alphanumeric())
+        .count();
+
+    if ascii_len > 0 && code.as_bytes().get(ascii_len).is_none_or(u8::is_ascii) {
+        return Some(ascii_len);
+    }
+
+    static IDENTIFIER: OnceLock<Regex> = OnceLock::new();
+    IDENTIFIER
+        .get_or_init(|| Regex::new(r"^[\p{XID_Start}_]\p{XID_Continue}*").unwrap())
+        .find(code)
+        .map(|found| found.end())
+}
+
+// Block comments can be nested, so they can't be matched with a regex
+fn block_comment_len(code: &str) -> Option<usize> {
+    let bytes = code.as_bytes();
+    let mut depth = 0;
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        if bytes[pos..].starts_with(b"/*") {
+            depth += 1;
+            pos += 2;
+        } else if bytes[pos..].starts_with(b"*/") {
+            depth -= 1;
+            pos += 2;
+
+            if depth == 0 {
+                return Some(pos);
+            }
+        } else {
+            pos += 1;
+        }
+    }
+
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    // Most tests only care about the text and type of each token
+    fn strip_locations(tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .map(|token| {
+                Token::new(
+                    token.text,
+                    token.token_type,
+                    CodeLocation::default(),
+                    Span::default(),
+                )
+            })
+            .collect()
+    }
+
     #[test]
     fn test_tokenize_basic() {
-        let loc = CodeLocation::new(usize::MAX, usize::MAX);
-        let result = tokenize("if   3 \n\twhile").unwrap();
+        let loc = CodeLocation::default();
+        let span = Span::default();
+        let result = strip_locations(tokenize("if   3 \n\twhile").unwrap());
 
         use TokenType::*;
         assert_eq!(
             result,
             vec!(
-                Token::new("if", Identifier, loc),
-                Token::new("3", Integer, loc),
-                Token::new("while", Identifier, loc),
+                Token::new("if", Keyword, loc, span),
+                Token::new("3", Integer, loc, span),
+                Token::new("while", Keyword, loc, span),
             )
         );
     }
@@ -105,122 +379,543 @@ mod tests {
         assert_eq!(
             result,
             vec!(
-                Token::new("if", Identifier, CodeLocation::new(1, 1)),
-                Token::new("3", Integer, CodeLocation::new(1, 4)),
-                Token::new("while", Identifier, CodeLocation::new(2, 3)),
+                Token::new("if", Keyword, CodeLocation::new(1, 1), Span::new(0, 2)),
+                Token::new("3", Integer, CodeLocation::new(1, 4), Span::new(3, 4)),
+                Token::new("while", Keyword, CodeLocation::new(2, 3), Span::new(7, 12)),
             )
         );
         assert_ne!(
             result,
             vec!(
-                Token::new("if", Identifier, CodeLocation::new(1, 1)),
-                Token::new("3", Integer, CodeLocation::new(1, 5)),
-                Token::new("while", Identifier, CodeLocation::new(2, 3)),
+                Token::new("if", Keyword, CodeLocation::new(1, 1), Span::new(0, 2)),
+                Token::new("3", Integer, CodeLocation::new(1, 5), Span::new(3, 4)),
+                Token::new("while", Keyword, CodeLocation::new(2, 3), Span::new(7, 12)),
             )
         );
         assert_ne!(
             result,
             vec!(
-                Token::new("if", Identifier, CodeLocation::new(1, 1)),
-                Token::new("3", Integer, CodeLocation::new(1, 4)),
-                Token::new("while", Identifier, CodeLocation::new(1, 3)),
+                Token::new("if", Keyword, CodeLocation::new(1, 1), Span::new(0, 2)),
+                Token::new("3", Integer, CodeLocation::new(1, 4), Span::new(3, 4)),
+                Token::new("while", Keyword, CodeLocation::new(1, 3), Span::new(7, 12)),
             )
         );
     }
 
+    #[test]
+    fn test_tokenize_keywords() {
+        let loc = CodeLocation::default();
+        let span = Span::default();
+        let result = strip_locations(tokenize("if iffy then_ not x or y").unwrap());
+
+        use TokenType::*;
+        assert_eq!(
+            result,
+            vec!(
+                Token::new("if", Keyword, loc, span),
+                Token::new("iffy", Identifier, loc, span),
+                Token::new("then_", Identifier, loc, span),
+                Token::new("not", Keyword, loc, span),
+                Token::new("x", Identifier, loc, span),
+                Token::new("or", Keyword, loc, span),
+                Token::new("y", Identifier, loc, span),
+            )
+        );
+    }
+
+    #[test]
+    fn test_tokenize_unicode() {
+        let result = tokenize("/* ä */ var ñandú = 1;\n  αβ_1 + ñandú").unwrap();
+
+        use TokenType::*;
+        assert_eq!(
+            result,
+            vec!(
+                Token::new("var", Keyword, CodeLocation::new(1, 9), Span::new(9, 12)),
+                Token::new(
+                    "ñandú",
+                    Identifier,
+                    CodeLocation::new(1, 13),
+                    Span::new(13, 20)
+                ),
+                Token::new("=", Operator, CodeLocation::new(1, 19), Span::new(21, 22)),
+                Token::new("1", Integer, CodeLocation::new(1, 21), Span::new(23, 24)),
+                Token::new(
+                    ";",
+                    Punctuation,
+                    CodeLocation::new(1, 22),
+                    Span::new(24, 25)
+                ),
+                Token::new(
+                    "αβ_1",
+                    Identifier,
+                    CodeLocation::new(2, 3),
+                    Span::new(28, 34)
+                ),
+                Token::new("+", Operator, CodeLocation::new(2, 8), Span::new(35, 36)),
+                Token::new(
+                    "ñandú",
+                    Identifier,
+                    CodeLocation::new(2, 10),
+                    Span::new(37, 44)
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn test_tokenize_unicode_invalid() {
+        let result = tokenize("ä + 🦀").unwrap_err();
+        assert_eq!(
+            format!("{}", result),
+            "TokenizerError: Invalid token starting with '🦀'"
+        );
+        assert_eq!(result.loc, CodeLocation::new(1, 5));
+
+        let result = tokenize("1x ¬").unwrap_err();
+        assert_eq!(
+            format!("{}", result),
+            "TokenizerError: Invalid token starting with '¬'"
+        );
+        assert_eq!(result.loc, CodeLocation::new(1, 4));
+    }
+
     #[test]
     fn test_tokenize_comment() {
-        let loc = CodeLocation::new(usize::MAX, usize::MAX);
-        let result = tokenize("if   3 \n\n//Comment\n#Another\n\twhile //Comment2").unwrap();
+        let loc = CodeLocation::default();
+        let span = Span::default();
+        let result = strip_locations(
+            tokenize("if   3 \n\n//Comment\n#Another\n\twhile //Comment2").unwrap(),
+        );
+
+        use TokenType::*;
+        assert_eq!(
+            result,
+            vec!(
+                Token::new("if", Keyword, loc, span),
+                Token::new("3", Integer, loc, span),
+                Token::new("while", Keyword, loc, span),
+            )
+        );
+    }
+
+    #[test]
+    fn test_tokenize_block_comment() {
+        let result =
+            tokenize("if /* Comment\n /* Nested\n */ still comment */ 3\n/**/while").unwrap();
 
         use TokenType::*;
         assert_eq!(
             result,
             vec!(
-                Token::new("if", Identifier, loc),
-                Token::new("3", Integer, loc),
-                Token::new("while", Identifier, loc),
+                Token::new("if", Keyword, CodeLocation::new(1, 1), Span::new(0, 2)),
+                Token::new("3", Integer, CodeLocation::new(3, 22), Span::new(46, 47)),
+                Token::new("while", Keyword, CodeLocation::new(4, 5), Span::new(52, 57)),
             )
         );
     }
 
+    #[test]
+    fn test_tokenize_comments() {
+        let result = tokenize_comments("1 # a\n/* b\n */ 2 // c").unwrap();
+
+        use TokenType::*;
+        assert_eq!(
+            result,
+            vec!(
+                Token::new("# a", Comment, CodeLocation::new(1, 3), Span::new(2, 5)),
+                Token::new(
+                    "/* b\n */",
+                    Comment,
+                    CodeLocation::new(2, 1),
+                    Span::new(6, 14)
+                ),
+                Token::new("// c", Comment, CodeLocation::new(3, 7), Span::new(17, 21)),
+            )
+        );
+    }
+
+    #[test]
+    fn test_tokenize_block_comment_unterminated() {
+        let result = tokenize("if 3\n  /* Comment /* Nested */\n\twhile").unwrap_err();
+        assert_eq!(
+            format!("{}", result),
+            "TokenizerError: Unterminated comment"
+        );
+    }
+
+    #[test]
+    fn test_tokenize_lossless() {
+        let (tokens, trailing_trivia) =
+            tokenize_lossless("  if /* a /* b */ */ 3 // c\n\twhile\n").unwrap();
+
+        let trivia = tokens
+            .iter()
+            .map(|token| token.leading_trivia)
+            .collect::<Vec<_>>();
+        assert_eq!(trivia, vec!["  ", " /* a /* b */ */ ", " // c\n\t"]);
+        assert_eq!(trailing_trivia, "\n");
+
+        let (tokens, trailing_trivia, _) = tokenize_with_trivia("  if 3 ", false).unwrap();
+        assert!(tokens.iter().all(|token| token.leading_trivia.is_empty()));
+        assert_eq!(trailing_trivia, "");
+    }
+
     #[test]
     fn test_tokenize_operators_basic() {
-        let loc = CodeLocation::new(usize::MAX, usize::MAX);
-        let result = tokenize("var = 1 + 2").unwrap();
+        let loc = CodeLocation::default();
+        let span = Span::default();
+        let result = strip_locations(tokenize("var = 1 + 2").unwrap());
 
         use TokenType::*;
         assert_eq!(
             result,
             vec!(
-                Token::new("var", Identifier, loc),
-                Token::new("=", Operator, loc),
-                Token::new("1", Integer, loc),
-                Token::new("+", Operator, loc),
-                Token::new("2", Integer, loc),
+                Token::new("var", Keyword, loc, span),
+                Token::new("=", Operator, loc, span),
+                Token::new("1", Integer, loc, span),
+                Token::new("+", Operator, loc, span),
+                Token::new("2", Integer, loc, span),
             )
         );
     }
 
     #[test]
     fn test_tokenize_operators_all() {
-        let loc = CodeLocation::new(usize::MAX, usize::MAX);
-        let result = tokenize("var 1 + - * 1/2 = == != < <= > >= 2 %").unwrap();
+        let loc = CodeLocation::default();
+        let span = Span::default();
+        let result = strip_locations(tokenize("var 1 + - * 1/2 = == != < <= > >= 2 %").unwrap());
 
         use TokenType::*;
         assert_eq!(
             result,
             vec!(
-                Token::new("var", Identifier, loc),
-                Token::new("1", Integer, loc),
-                Token::new("+", Operator, loc),
-                Token::new("-", Operator, loc),
-                Token::new("*", Operator, loc),
-                Token::new("1", Integer, loc),
-                Token::new("/", Operator, loc),
-                Token::new("2", Integer, loc),
-                Token::new("=", Operator, loc),
-                Token::new("==", Operator, loc),
-                Token::new("!=", Operator, loc),
-                Token::new("<", Operator, loc),
-                Token::new("<=", Operator, loc),
-                Token::new(">", Operator, loc),
-                Token::new(">=", Operator, loc),
-                Token::new("2", Integer, loc),
-                Token::new("%", Operator, loc),
+                Token::new("var", Keyword, loc, span),
+                Token::new("1", Integer, loc, span),
+                Token::new("+", Operator, loc, span),
+                Token::new("-", Operator, loc, span),
+                Token::new("*", Operator, loc, span),
+                Token::new("1", Integer, loc, span),
+                Token::new("/", Operator, loc, span),
+                Token::new("2", Integer, loc, span),
+                Token::new("=", Operator, loc, span),
+                Token::new("==", Operator, loc, span),
+                Token::new("!=", Operator, loc, span),
+                Token::new("<", Operator, loc, span),
+                Token::new("<=", Operator, loc, span),
+                Token::new(">", Operator, loc, span),
+                Token::new(">=", Operator, loc, span),
+                Token::new("2", Integer, loc, span),
+                Token::new("%", Operator, loc, span),
             )
         );
     }
 
     #[test]
     fn test_tokenize_punctuation_basic() {
-        let loc = CodeLocation::new(usize::MAX, usize::MAX);
-        let result = tokenize("{var = (1 + 2, 3);:}").unwrap();
+        let loc = CodeLocation::default();
+        let span = Span::default();
+        let result = strip_locations(tokenize("{var = (1 + 2, 3);:}").unwrap());
 
         use TokenType::*;
         assert_eq!(
             result,
             vec!(
-                Token::new("{", Punctuation, loc),
-                Token::new("var", Identifier, loc),
-                Token::new("=", Operator, loc),
-                Token::new("(", Punctuation, loc),
-                Token::new("1", Integer, loc),
-                Token::new("+", Operator, loc),
-                Token::new("2", Integer, loc),
-                Token::new(",", Punctuation, loc),
-                Token::new("3", Integer, loc),
-                Token::new(")", Punctuation, loc),
-                Token::new(";", Punctuation, loc),
-                Token::new(":", Punctuation, loc),
-                Token::new("}", Punctuation, loc),
+                Token::new("{", Punctuation, loc, span),
+                Token::new("var", Keyword, loc, span),
+                Token::new("=", Operator, loc, span),
+                Token::new("(", Punctuation, loc, span),
+                Token::new("1", Integer, loc, span),
+                Token::new("+", Operator, loc, span),
+                Token::new("2", Integer, loc, span),
+                Token::new(",", Punctuation, loc, span),
+                Token::new("3", Integer, loc, span),
+                Token::new(")", Punctuation, loc, span),
+                Token::new(";", Punctuation, loc, span),
+                Token::new(":", Punctuation, loc, span),
+                Token::new("}", Punctuation, loc, span),
             )
         );
     }
 
+    #[test]
+    fn test_tokenize_integer_literals() {
+        let loc = CodeLocation::default();
+        let span = Span::default();
+        let result = strip_locations(tokenize("0xFF 0b1010 0o17 1_000_000 0x_A").unwrap());
+
+        use TokenType::*;
+        assert_eq!(
+            result,
+            vec!(
+                Token::new("0xFF", Integer, loc, span),
+                Token::new("0b1010", Integer, loc, span),
+                Token::new("0o17", Integer, loc, span),
+                Token::new("1_000_000", Integer, loc, span),
+                Token::new("0x_A", Integer, loc, span),
+            )
+        );
+    }
+
+    #[test]
+    fn test_tokenize_malformed_integer_literals() {
+        let no_digits = |literal, prefix| {
+            format!("Integer literal {literal} has no digits after its {prefix} prefix")
+        };
+        let underscores = |literal| {
+            format!("Integer literal {literal} can't end in an underscore or have two in a row")
+        };
+        let cases = [
+            ("0xz", no_digits("0xz", "0x")),
+            ("0x", no_digits("0x", "0x")),
+            ("0b2", no_digits("0b2", "0b")),
+            ("0o_", no_digits("0o_", "0o")),
+            ("1_", underscores("1_")),
+            ("1__2", underscores("1__2")),
+            ("0xF_", underscores("0xF_")),
+        ];
+
+        for (literal, msg) in cases {
+            let error = tokenize(&format!("1 + {literal};")).unwrap_err();
+            assert_eq!(error.code, ErrorCode::InvalidIntegerLiteral);
+            assert_eq!(error.msg, msg);
+            assert_eq!(error.span, Span::new(4, 4 + literal.len()));
+        }
+    }
+
+    #[test]
+    fn test_tokenize_custom_operators() {
+        let loc = CodeLocation::default();
+        let span = Span::default();
+        let result =
+            strip_locations(tokenize("a <+> b; infixl 5 <+> = f; a <+> b <= c <==> d").unwrap());
+
+        use TokenType::*;
+        assert_eq!(
+            result,
+            vec!(
+                Token::new("a", Identifier, loc, span),
+                Token::new("<", Operator, loc, span),
+                Token::new("+", Operator, loc, span),
+                Token::new(">", Operator, loc, span),
+                Token::new("b", Identifier, loc, span),
+                Token::new(";", Punctuation, loc, span),
+                Token::new("infixl", Keyword, loc, span),
+                Token::new("5", Integer, loc, span),
+                Token::new("<+>", Operator, loc, span),
+                Token::new("=", Operator, loc, span),
+                Token::new("f", Identifier, loc, span),
+                Token::new(";", Punctuation, loc, span),
+                Token::new("a", Identifier, loc, span),
+                Token::new("<+>", Operator, loc, span),
+                Token::new("b", Identifier, loc, span),
+                Token::new("<=", Operator, loc, span),
+                Token::new("c", Identifier, loc, span),
+                Token::new("<=", Operator, loc, span),
+                Token::new("=", Operator, loc, span),
+                Token::new(">", Operator, loc, span),
+                Token::new("d", Identifier, loc, span),
+            )
+        );
+
+        let result = strip_locations(tokenize("infix 4 <==>// Comment\n = f; a <==> b").unwrap());
+        assert_eq!(result[2], Token::new("<==>", Operator, loc, span));
+        assert_eq!(result[7], Token::new("<==>", Operator, loc, span));
+
+        // Only valid declarations are recognized
+        let operators = |code| {
+            tokenize(code)
+                .unwrap()
+                .into_iter()
+                .filter(|token| token.token_type == Operator)
+                .map(|token| token.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            operators("infixl 100 <+> = f; a <+> b"),
+            vec!["<+>", "=", "<", "+", ">"]
+        );
+        assert_eq!(
+            operators("infixl 5 <+> = 1; a <+> b"),
+            vec!["<+>", "=", "<", "+", ">"]
+        );
+
+        // Declarations in a block apply after it as well
+        assert_eq!(
+            operators("{ infixl 5 <+> = f }; a <+> b"),
+            vec!["<+>", "=", "<+>"]
+        );
+    }
+
     #[test]
     #[should_panic]
     fn test_tokenize_wrong_token() {
         tokenize("if 3\n  while @").unwrap();
     }
+
+    // The original regex based tokenizer, kept as a reference for the hand-written scanner
+    fn tokenize_with_regexes(code: &str) -> Result<Vec<Token<'_>>, TokenizerError> {
+        let regexes = [
+            (TokenType::Comment, Regex::new(r"^(//|#).*").unwrap()),
+            (TokenType::Whitespace, Regex::new(r"^[\s\t\n]+").unwrap()),
+            (
+                TokenType::Operator,
+                Regex::new(r"^(==|!=|<=|>=|=|<|>|\+|-|\*|/|\%)").unwrap(),
+            ),
+            (TokenType::Punctuation, Regex::new(r"^[\(\){},;:]").unwrap()),
+            (
+                TokenType::Integer,
+                Regex::new(r"^(0x[0-9a-fA-F_]+|0b[01_]+|0o[0-7_]+|[0-9][0-9_]*)").unwrap(),
+            ),
+            (
+                TokenType::Identifier,
+                Regex::new(r"^[\p{XID_Start}_]\p{XID_Continue}*").unwrap(),
+            ),
+        ];
+
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        let mut line_number = 1;
+        let mut column = 1;
+
+        while pos < code.len() {
+            let loc = CodeLocation::new(line_number, column);
+
+            let token_len = if code[pos..].starts_with("/*") {
+                block_comment_len(&code[pos..]).ok_or_else(|| TokenizerError {
+                    code: ErrorCode::UnterminatedComment,
+                    msg: String::from("Unterminated comment"),
+                    loc,
+                    span: Span::new(pos, pos + 2),
+                })?
+            } else {
+                let found = regexes.iter().find_map(|(token_type, regex_matcher)| {
+                    regex_matcher
+                        .find(&code[pos..])
+                        .map(|token| (*token_type, token.end()))
+                });
+                let Some((token_type, token_len)) = found else {
+                    let first_char = code[pos..].chars().next().unwrap_or_default();
+                    return Err(TokenizerError {
+                        code: ErrorCode::InvalidToken,
+                        msg: format!("Invalid token starting with '{}'", first_char),
+                        loc,
+                        span: Span::new(pos, pos + first_char.len_utf8()),
+                    });
+                };
+
+                if !token_type.ignore() {
+                    let text = &code[pos..pos + token_len];
+                    let token_type =
+                        if token_type == TokenType::Identifier && KEYWORDS.contains(&text) {
+                            TokenType::Keyword
+                        } else {
+                            token_type
+                        };
+                    let span = Span::new(pos, pos + token_len);
+                    tokens.push(Token::new(text, token_type, loc, span));
+                }
+                token_len
+            };
+
+            for c in code[pos..pos + token_len].chars() {
+                if c == '\n' {
+                    line_number += 1;
+                    column = 1;
+                } else {
+                    column += 1;
+                }
+            }
+            pos += token_len;
+        }
+
+        Ok(tokens)
+    }
+
+    #[test]
+    fn test_tokenize_matches_regexes() {
+        let programs = [
+            "var x: Int = 0x1F_ff + 0b10 * 0o7_7 - 1_000 % 0x_a;",
+            "if a<=b then {c>=d} else { e != f == g < h > i; j = k / l }",
+            "// Comment\n# Another\n/* Block /* nested */ */ while true do {}",
+            "  \t\r\n\u{a0}\u{2003}x\u{3000}y  ",
+            "_a1 a_1 ñandú aé αβ_1 ä_ x̃ _ __init__",
+            "f(a, b); g(); defer print(1); macro m(x) { x }",
+            "0 00 0_0 9a",
+            "a # comment /* not a block\nb // comment\r\nc",
+            "ä + 🦀",
+            "1x ¬",
+            "if 3\n  /* Comment /* Nested */\n\twhile",
+            "x ! y",
+            "a @",
+            "ab\u{301}c ç",
+            "",
+        ];
+
+        for code in programs {
+            match (tokenize(code), tokenize_with_regexes(code)) {
+                (Ok(tokens), Ok(expected)) => assert_eq!(tokens, expected, "{code}"),
+                (Err(error), Err(expected)) => {
+                    assert_eq!(error.to_string(), expected.to_string(), "{code}")
+                }
+                (result, expected) => panic!("{code}: {result:?} != {expected:?}"),
+            }
+        }
+    }
+
+    // Run with: cargo test --release bench_tokenize -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_tokenize() {
+        use std::time::Instant;
+
+        let snippet = "var counter: Int = 0x_FF; // Count things\n\
+                       while counter > 0 do {\n\
+                       \x20   if counter % 2 == 0 then { print_int(counter) } else { f(counter, true) };\n\
+                       \x20   counter = counter - 1; /* Block comment */\n\
+                       }\n";
+        let code = snippet.repeat(20_000);
+        let megabytes = code.len() as f64 / 1_000_000.0;
+
+        let start = Instant::now();
+        let tokens = tokenize(&code).unwrap();
+        let scanner_time = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let expected = tokenize_with_regexes(&code).unwrap();
+        let regex_time = start.elapsed().as_secs_f64();
+
+        assert_eq!(tokens, expected);
+        println!(
+            "Tokenized {:.1} MB into {} tokens\n  scanner: {:.3} s, {:.1} MB/s\n  regexes: {:.3} s, {:.1} MB/s",
+            megabytes,
+            tokens.len(),
+            scanner_time,
+            megabytes / scanner_time,
+            regex_time,
+            megabytes / regex_time
+        );
+
+        // Batch jobs tokenize many small programs, where setup costs dominate
+        let programs = 2_000;
+
+        let start = Instant::now();
+        for _ in 0..programs {
+            tokenize(snippet).unwrap();
+        }
+        let scanner_time = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        for _ in 0..programs {
+            tokenize_with_regexes(snippet).unwrap();
+        }
+        let regex_time = start.elapsed().as_secs_f64();
+
+        println!(
+            "Tokenized {} small programs\n  scanner: {:.0} programs/s\n  regexes: {:.0} programs/s",
+            programs,
+            programs as f64 / scanner_time,
+            programs as f64 / regex_time
+        );
+    }
 }
@@ -1,199 +1,432 @@
-use std::{error::Error, fmt::Display};
-
 use crate::compiler::{
     ast::{AstNode, Expression::*, TypeExpression},
+    diagnostic::Diagnostic,
+    error_codes::ErrorCode,
     symtab::SymTab,
+    token::{CodeLocation, Span},
     variable::Type,
 };
 
-#[derive(Debug)]
-pub struct TypeCheckerError {
-    msg: String,
-}
-
-impl Display for TypeCheckerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "TypeCheckerError: {}", self.msg)
+// Checks the whole tree, reporting every type error in source order. Expressions with an error
+// get the error type, so that mistakes aren't reported again by the expressions around them.
+pub fn type_check<'source>(
+    ast: &mut AstNode<'source>,
+    symbols: &mut SymTab<'source, Type>,
+) -> Result<Type, Vec<Diagnostic>> {
+    let mut errors = Vec::new();
+    let node_type = check(ast, symbols, &mut errors);
+
+    if errors.is_empty() {
+        Ok(node_type)
+    } else {
+        Err(errors)
     }
 }
 
-impl Error for TypeCheckerError {}
-
-pub fn type_check<'source>(
+fn check<'source>(
     ast: &mut AstNode<'source>,
     symbols: &mut SymTab<'source, Type>,
-) -> Result<Type, Box<dyn Error>> {
-    let node_type = get_type(ast, symbols)?;
+    errors: &mut Vec<Diagnostic>,
+) -> Type {
+    let node_type = get_type(ast, symbols, errors);
     ast.node_type = node_type.clone();
-    Ok(node_type)
+    node_type
 }
 
 fn get_type<'source>(
     ast: &mut AstNode<'source>,
     symbols: &mut SymTab<'source, Type>,
-) -> Result<Type, Box<dyn Error>> {
+    errors: &mut Vec<Diagnostic>,
+) -> Type {
+    let (loc, span) = (ast.loc, ast.span);
+    let located = |code, msg| Diagnostic::error(msg, loc, span).with_code(code);
+
     match &mut ast.expr {
-        EmptyLiteral() => Ok(Type::Unit),
-        IntLiteral(_) => Ok(Type::Int),
-        BoolLiteral(_) => Ok(Type::Bool),
-        Identifier(name) => Ok(symbols.get(name)?.clone()),
+        EmptyLiteral() => Type::Unit,
+        IntLiteral(val) => {
+            if i64::try_from(*val).is_err() {
+                errors.push(located(
+                    ErrorCode::IntegerOutOfRange,
+                    format!("Integer literal {} does not fit in type {}", val, Type::Int),
+                ));
+            }
+            Type::Int
+        }
+        BoolLiteral(_) => Type::Bool,
+        Identifier(name) => match symbols.get(name) {
+            // Which overload is meant is only known from the arguments of a call
+            Ok(Type::Overloaded(_)) => {
+                errors.push(located(
+                    ErrorCode::OverloadedValue,
+                    format!("{} is overloaded and can only be called", name),
+                ));
+                Type::Error
+            }
+            // Functions have no value at run time in compiled code
+            Ok(Type::Func(_, _)) => {
+                errors.push(located(
+                    ErrorCode::FunctionValue,
+                    format!("{} is a function and can only be called", name),
+                ));
+                Type::Error
+            }
+            Ok(symbol_type) => symbol_type.clone(),
+            Err(error) => {
+                errors.push(error.located(loc, span));
+                Type::Error
+            }
+        },
         UnaryOp(op, ref mut expr) => {
-            let expr_types = vec![type_check(expr, symbols)?];
-
-            let Type::Func(sig_arg_types, sig_ret_type) = symbols.get(&format!("unary_{op}"))?
-            else {
-                return Err(Box::new(TypeCheckerError {
-                    msg: format!("Identifier {} does not correspond to an operator!", op),
-                }));
+            // The smallest Int can only be written as a negated literal, which is out of range
+            // on its own
+            let expr_types = match expr.expr {
+                IntLiteral(val) if *op == "-" && val == i64::MIN.unsigned_abs() as i128 => {
+                    expr.node_type = Type::Int;
+                    vec![Type::Int]
+                }
+                _ => vec![check(expr, symbols, errors)],
             };
-
-            if expr_types != *sig_arg_types {
-                return Err(Box::new(TypeCheckerError {
-                    msg: format!(
-                        "Operator {} argument types {:?} don't match expected {:?}",
-                        op, expr_types, *sig_arg_types
-                    ),
-                }));
-            }
-
-            Ok((**sig_ret_type).clone())
+            check_call(
+                &format!("unary_{op}"),
+                ("operator", op),
+                expr_types,
+                vec![(expr.loc, expr.span)],
+                symbols,
+                &mut ast.overload,
+                (loc, span),
+            )
+            .unwrap_or_else(|error| {
+                errors.push(*error);
+                Type::Error
+            })
         }
         BinaryOp(ref mut left, op, ref mut right) => match *op {
-            "==" | "!=" => {
-                let left_type = type_check(left, symbols)?;
-                let right_type = type_check(right, symbols)?;
-                if left_type != right_type {
-                    return Err(Box::new(TypeCheckerError {
-                        msg: format!("Mismatched types being compared with {op}"),
-                    }));
-                }
-                Ok(Type::Bool)
-            }
             "=" => {
-                if !matches!(left.expr, Identifier(_)) {
-                    return Err(Box::new(TypeCheckerError {
-                        msg: String::from("Non-variable on left side of assignment!"),
-                    }));
-                }
+                let left_type = check(left, symbols, errors);
+                let right_type = check(right, symbols, errors);
 
-                let left_type = type_check(left, symbols)?;
-                let right_type = type_check(right, symbols)?;
-                if left_type != right_type {
-                    return Err(Box::new(TypeCheckerError {
-                        msg: String::from("Mismatched types in assignment!"),
-                    }));
+                if !matches!(left.expr, Identifier(_)) {
+                    errors.push(
+                        Diagnostic::error(
+                            String::from("Non-variable on left side of assignment!"),
+                            left.loc,
+                            left.span,
+                        )
+                        .with_code(ErrorCode::InvalidAssignmentTarget),
+                    );
+                    Type::Error
+                } else if !left_type.accepts(&right_type) {
+                    errors.push(
+                        located(
+                            ErrorCode::MismatchedAssignment,
+                            format!(
+                                "Mismatched types in assignment: expected {}, found {}",
+                                left_type, right_type
+                            ),
+                        )
+                        .with_label(
+                            left.loc,
+                            left.span,
+                            format!("Variable has type {}", left_type),
+                        )
+                        .with_label(
+                            right.loc,
+                            right.span,
+                            format!("Found {}", right_type),
+                        ),
+                    );
+                    Type::Error
+                } else {
+                    left_type
                 }
-                Ok(left_type)
             }
             _ => {
-                let left_type = type_check(left, symbols)?;
-                let right_type = type_check(right, symbols)?;
+                let left_type = check(left, symbols, errors);
+                let right_type = check(right, symbols, errors);
                 let arg_types = vec![left_type, right_type];
-
-                let Type::Func(sig_arg_types, sig_ret_type) = symbols.get(op).unwrap() else {
-                    return Err(Box::new(TypeCheckerError {
-                        msg: format!("Identifier {} does not correspond to an operator!", op),
-                    }));
-                };
-
-                if arg_types != *sig_arg_types {
-                    return Err(Box::new(TypeCheckerError {
-                        msg: format!(
-                            "Operator {} argument types {:?} don't match expected {:?}",
-                            op, arg_types, *sig_arg_types
-                        ),
-                    }));
-                }
-
-                Ok((**sig_ret_type).clone())
+                let arg_locations = vec![(left.loc, left.span), (right.loc, right.span)];
+                check_call(
+                    op,
+                    ("operator", op),
+                    arg_types,
+                    arg_locations,
+                    symbols,
+                    &mut ast.overload,
+                    (loc, span),
+                )
+                .unwrap_or_else(|error| {
+                    errors.push(*error);
+                    Type::Error
+                })
             }
         },
         VarDeclaration(name, ref mut expr, ref mut type_expr) => {
-            let type_var = type_check(expr, symbols)?;
+            let mut type_var = check(expr, symbols, errors);
 
             if let Some(type_expr) = type_expr {
-                let expected_type = match type_expr {
-                    TypeExpression::Int(_) => Type::Int,
-                    TypeExpression::Bool(_) => Type::Bool,
+                let (expected_type, type_loc, type_span) = match *type_expr {
+                    TypeExpression::Int(loc, span) => (Type::Int, loc, span),
+                    TypeExpression::Bool(loc, span) => (Type::Bool, loc, span),
                 };
 
-                if type_var != expected_type {
-                    return Err(Box::new(TypeCheckerError {
-                        msg: format!(
-                            "Expected type {:?} does not match actual type {:?} in var declaration",
-                            expected_type, type_var
+                if !expected_type.accepts(&type_var) {
+                    errors.push(
+                        located(
+                            ErrorCode::MismatchedDeclaration,
+                            format!(
+                                "Mismatched types in var declaration: expected {}, found {}",
+                                expected_type, type_var
+                            ),
+                        )
+                        .with_label(
+                            type_loc,
+                            type_span,
+                            format!("Declared as {}", expected_type),
+                        )
+                        .with_label(
+                            expr.loc,
+                            expr.span,
+                            format!("Found {}", type_var),
                         ),
-                    }));
+                    );
                 }
+                // Later uses are checked against the declared type
+                type_var = expected_type;
             }
 
-            symbols.insert(name, type_var)?;
-            Ok(Type::Unit)
+            if let Err(error) = symbols.insert(name, type_var) {
+                errors.push(error.located(loc, span));
+            }
+            Type::Unit
         }
         Conditional(ref mut condition_expr, ref mut then_expr, ref mut else_expr) => {
-            if !matches!(type_check(condition_expr, symbols)?, Type::Bool) {
-                return Err(Box::new(TypeCheckerError {
-                    msg: String::from("Non-bool as if-then-else condition!"),
-                }));
+            let condition_type = check(condition_expr, symbols, errors);
+            if !Type::Bool.accepts(&condition_type) {
+                errors.push(
+                    Diagnostic::error(
+                        format!(
+                            "Non-bool as if-then-else condition: expected Bool, found {}",
+                            condition_type
+                        ),
+                        condition_expr.loc,
+                        condition_expr.span,
+                    )
+                    .with_code(ErrorCode::NonBoolCondition),
+                );
             }
 
+            let then_type = check(then_expr, symbols, errors);
             if let Some(ref mut else_expr) = else_expr {
-                let then_type = type_check(then_expr, symbols)?;
-                let else_type = type_check(else_expr, symbols)?;
-                if then_type == else_type {
-                    Ok(then_type)
+                let else_type = check(else_expr, symbols, errors);
+                if then_type == Type::Error {
+                    else_type
+                } else if then_type.accepts(&else_type) {
+                    then_type
                 } else {
-                    Err(Box::new(TypeCheckerError {
-                        msg: String::from("Mismatched return types in if-then-else!"),
-                    }))
+                    errors.push(
+                        located(
+                            ErrorCode::MismatchedBranches,
+                            format!(
+                                "Mismatched types in if-then-else branches: expected {}, found {}",
+                                then_type, else_type
+                            ),
+                        )
+                        .with_label(
+                            then_expr.loc,
+                            then_expr.span,
+                            format!("Expected {} because of this branch", then_type),
+                        )
+                        .with_label(
+                            else_expr.loc,
+                            else_expr.span,
+                            format!("Found {}", else_type),
+                        ),
+                    );
+                    Type::Error
                 }
             } else {
-                Ok(Type::Unit)
+                Type::Unit
             }
         }
         While(ref mut condition_expr, ref mut do_expr) => {
-            if !matches!(type_check(condition_expr, symbols)?, Type::Bool) {
-                return Err(Box::new(TypeCheckerError {
-                    msg: String::from("Non-bool as while-do condition!"),
-                }));
+            let condition_type = check(condition_expr, symbols, errors);
+            if !Type::Bool.accepts(&condition_type) {
+                errors.push(
+                    Diagnostic::error(
+                        format!(
+                            "Non-bool as while-do condition: expected Bool, found {}",
+                            condition_type
+                        ),
+                        condition_expr.loc,
+                        condition_expr.span,
+                    )
+                    .with_code(ErrorCode::NonBoolCondition),
+                );
             }
-            type_check(do_expr, symbols)?;
-            Ok(Type::Unit)
+            check(do_expr, symbols, errors);
+            Type::Unit
         }
         FunCall(name, args) => {
             let mut arg_types = Vec::new();
-            for arg in args {
-                arg_types.push(type_check(arg, symbols)?);
+            for arg in args.iter_mut() {
+                arg_types.push(check(arg, symbols, errors));
             }
+            let arg_locations = args.iter().map(|arg| (arg.loc, arg.span)).collect();
+            check_call(
+                name,
+                ("function", name),
+                arg_types,
+                arg_locations,
+                symbols,
+                &mut ast.overload,
+                (loc, span),
+            )
+            .unwrap_or_else(|error| {
+                errors.push(*error);
+                Type::Error
+            })
+        }
+        Block(ref mut expressions) => {
+            symbols.push_level();
 
-            let Type::Func(sig_arg_types, sig_ret_type) = symbols.get(name).unwrap() else {
-                return Err(Box::new(TypeCheckerError {
-                    msg: format!("Identifier {} does not correspond to a function!", name),
-                }));
-            };
+            let mut type_var = Type::Unit;
+            for expression in expressions {
+                type_var = check(expression, symbols, errors);
+            }
+
+            symbols.remove_level();
+            type_var
+        }
+        Defer(ref mut expr) => {
+            check(expr, symbols, errors);
+            Type::Unit
+        }
+        MacroDefinition(name, _, _) => {
+            errors.push(located(
+                ErrorCode::UnexpandedMacro,
+                format!("Macro {} was not expanded before type checking!", name),
+            ));
+            Type::Error
+        }
+        // Already reported by the parser, the rest of a partial AST can still be checked
+        Error(_) => Type::Error,
+    }
+}
 
-            if arg_types != *sig_arg_types {
-                return Err(Box::new(TypeCheckerError {
-                    msg: format!(
-                        "Function {} argument types {:?} don't match expected {:?}",
-                        name, arg_types, *sig_arg_types
+// Overloaded symbols are resolved by the argument types, and the chosen overload is recorded.
+// Arguments with the error type match any signature, but can't be used to pick an overload.
+// Errors point at the arguments that don't match, with their types. They name the callee as
+// written, which for unary operators isn't the symbol they are looked up by.
+fn check_call<'source>(
+    symbol: &str,
+    (kind, name): (&str, &str),
+    arg_types: Vec<Type>,
+    arg_locations: Vec<(CodeLocation, Span)>,
+    symbols: &mut SymTab<'source, Type>,
+    overload: &mut Option<&'source str>,
+    (loc, span): (CodeLocation, Span),
+) -> Result<Type, Box<Diagnostic>> {
+    let located = |code, msg| Box::new(Diagnostic::error(msg, loc, span).with_code(code));
+    let matches = |sig_arg_types: &Vec<Type>| {
+        sig_arg_types.len() == arg_types.len()
+            && sig_arg_types
+                .iter()
+                .zip(arg_types.iter())
+                .all(|(sig_arg_type, arg_type)| sig_arg_type.accepts(arg_type))
+    };
+
+    match symbols
+        .get(symbol)
+        .map_err(|error| Box::new(error.located(loc, span)))?
+    {
+        Type::Func(sig_arg_types, sig_ret_type) => {
+            if sig_arg_types.len() != arg_types.len() {
+                return Err(located(
+                    ErrorCode::ArgumentCount,
+                    format!(
+                        "Wrong number of arguments for {} {}: expected {}, found {}",
+                        kind,
+                        name,
+                        sig_arg_types.len(),
+                        arg_types.len()
+                    ),
+                ));
+            }
+            if !matches(sig_arg_types) {
+                let error = located(
+                    ErrorCode::MismatchedArguments,
+                    format!(
+                        "Mismatched argument types for {} {}: expected {}, found {}",
+                        kind,
+                        name,
+                        Type::list(sig_arg_types),
+                        Type::list(&arg_types)
                     ),
-                }));
+                );
+                let mismatched = sig_arg_types
+                    .iter()
+                    .zip(arg_types.iter().zip(arg_locations))
+                    .filter(|(sig_arg_type, (arg_type, _))| !sig_arg_type.accepts(arg_type));
+                return Err(Box::new(mismatched.fold(
+                    *error,
+                    |error, (sig_arg_type, (arg_type, (loc, span)))| {
+                        let msg = format!("Expected {}, found {}", sig_arg_type, arg_type);
+                        error.with_label(loc, span, msg)
+                    },
+                )));
             }
 
             Ok((**sig_ret_type).clone())
         }
-        Block(ref mut expressions) => {
-            symbols.push_level();
+        Type::Overloaded(overloads) => {
+            if arg_types.contains(&Type::Error) {
+                return Ok(Type::Error);
+            }
 
-            let mut type_var = Type::Unit;
-            for expression in expressions {
-                type_var = type_check(expression, symbols)?;
+            for (symbol, signature) in overloads.iter() {
+                if let Type::Func(sig_arg_types, sig_ret_type) = signature {
+                    if matches(sig_arg_types) {
+                        *overload = Some(symbol);
+                        return Ok((**sig_ret_type).clone());
+                    }
+                }
             }
 
-            symbols.remove_level();
-            Ok(type_var)
+            let error = located(
+                ErrorCode::NoMatchingOverload,
+                format!(
+                    "No overload of {} {} matches argument types {}",
+                    kind,
+                    name,
+                    Type::list(&arg_types)
+                ),
+            );
+            let error = arg_types
+                .iter()
+                .zip(arg_locations)
+                .fold(*error, |error, (arg_type, (loc, span))| {
+                    error.with_label(loc, span, arg_type.to_string())
+                });
+            let candidates = overloads
+                .iter()
+                .filter_map(|(_, signature)| match signature {
+                    Type::Func(sig_arg_types, _) => Some(sig_arg_types),
+                    _ => None,
+                });
+            Err(Box::new(candidates.fold(error, |error, sig_arg_types| {
+                error.with_note(format!(
+                    "{} {} takes {}",
+                    kind,
+                    name,
+                    Type::list(sig_arg_types)
+                ))
+            })))
         }
+        Type::Error => Ok(Type::Error),
+        _ => Err(located(
+            ErrorCode::NotAFunction,
+            format!("Identifier {} does not correspond to a {}!", name, kind),
+        )),
     }
 }
 
@@ -226,6 +459,36 @@ mod tests {
         assert_eq!(result, Unit);
     }
 
+    #[test]
+    fn test_int_literal_range() {
+        let result = get_type("9223372036854775807");
+        assert_eq!(result, Int);
+
+        let result = get_type("-9223372036854775808");
+        assert_eq!(result, Int);
+
+        let result = get_type("0x7FFF_FFFF_FFFF_FFFF");
+        assert_eq!(result, Int);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_int_literal_too_large() {
+        get_type("9223372036854775808");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_int_literal_too_small() {
+        get_type("-9223372036854775809");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_int_literal_hex_too_large() {
+        get_type("0xFFFF_FFFF_FFFF_FFFF");
+    }
+
     #[test]
     fn test_var_untyped() {
         let result = get_type("var a = 1");
@@ -363,6 +626,106 @@ mod tests {
         assert_eq!(result, Unit);
     }
 
+    #[test]
+    fn test_defer() {
+        let result = get_type("{ var a = 1; defer a = 2; a }");
+        assert_eq!(result, Int);
+
+        let result = get_type("{ defer 1 }");
+        assert_eq!(result, Unit);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_defer_undefined() {
+        get_type("{ defer a = 2; var a = 1; }");
+    }
+
+    #[test]
+    fn test_assert() {
+        let result = get_type("assert(1 < 2)");
+        assert_eq!(result, Unit);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_assert_non_bool() {
+        get_type("assert(1)");
+    }
+
+    #[test]
+    fn test_process_builtins() {
+        let result = get_type("exit(1)");
+        assert_eq!(result, Unit);
+
+        let result = get_type("arg_count()");
+        assert_eq!(result, Int);
+
+        let result = get_type("arg_int(0)");
+        assert_eq!(result, Int);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_exit_non_int() {
+        get_type("exit(true)");
+    }
+
+    #[test]
+    fn test_overloads() {
+        let result = get_type("print(1)");
+        assert_eq!(result, Unit);
+
+        let result = get_type("print(1 == 2)");
+        assert_eq!(result, Unit);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_overloads_no_match() {
+        get_type("print({})");
+    }
+
+    #[test]
+    fn test_overload_recorded() {
+        let tokens = tokenize("print(true == false)").unwrap();
+        let mut ast = parse(&tokens).unwrap();
+        type_check(&mut ast, &mut SymTab::new_type_table()).unwrap();
+        assert_eq!(ast.overload, Some("print_bool"));
+
+        let FunCall(_, args) = &ast.expr else {
+            panic!("Expected a function call!");
+        };
+        assert_eq!(args[0].overload, Some("==_bool"));
+    }
+
+    #[test]
+    fn test_overloaded_value() {
+        let tokens = tokenize("var x = print; 1").unwrap();
+        let mut ast = parse(&tokens).unwrap();
+        let errors = type_check(&mut ast, &mut SymTab::new_type_table()).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].msg, "print is overloaded and can only be called");
+        assert_eq!(errors[0].code, Some(ErrorCode::OverloadedValue));
+        assert_eq!(errors[0].primary.span, Span::new(8, 13));
+    }
+
+    #[test]
+    fn test_function_value() {
+        let tokens = tokenize("var f = print_int; 1").unwrap();
+        let mut ast = parse(&tokens).unwrap();
+        let errors = type_check(&mut ast, &mut SymTab::new_type_table()).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].msg,
+            "print_int is a function and can only be called"
+        );
+        assert_eq!(errors[0].code, Some(ErrorCode::FunctionValue));
+        assert_eq!(errors[0].primary.span, Span::new(8, 17));
+    }
+
     #[test]
     fn test_function() {
         let mut tokens = tokenize("foo(1)").unwrap();
@@ -396,6 +759,240 @@ mod tests {
         type_check(&mut ast, &mut symtab).unwrap();
     }
 
+    #[test]
+    fn test_error_locations() {
+        let check = |code| {
+            let tokens = tokenize(code).unwrap();
+            let mut ast = parse(&tokens).unwrap();
+            type_check(&mut ast, &mut SymTab::new_type_table())
+                .unwrap_err()
+                .remove(0)
+        };
+
+        let error = check("var a = 1;\nwhile a do 2");
+        assert_eq!(error.primary.loc, CodeLocation::new(2, 7));
+        assert_eq!(error.primary.span, Span::new(17, 18));
+
+        let error = check("1 + b");
+        assert_eq!(error.msg, "No symbol b found!");
+        assert_eq!(error.primary.span, Span::new(4, 5));
+
+        let error = check("if true then 1 else false");
+        assert_eq!(error.primary.span, Span::new(0, 25));
+        assert_eq!(error.secondary[0].span, Span::new(13, 14));
+        assert_eq!(error.secondary[1].span, Span::new(20, 25));
+    }
+
+    #[test]
+    fn test_type_display() {
+        assert_eq!(Int.to_string(), "Int");
+        assert_eq!(
+            Func(vec![Int, Bool], Box::new(Unit)).to_string(),
+            "(Int, Bool) => Unit"
+        );
+        assert_eq!(Func(vec![], Box::new(Int)).to_string(), "() => Int");
+        assert_eq!(
+            SymTab::new_type_table().get("print").unwrap().to_string(),
+            "(Int) => Unit | (Bool) => Unit"
+        );
+    }
+
+    #[test]
+    fn test_mismatch_labels() {
+        let check = |code| {
+            let tokens = tokenize(code).unwrap();
+            let mut ast = parse(&tokens).unwrap();
+            let error = type_check(&mut ast, &mut SymTab::new_type_table())
+                .unwrap_err()
+                .remove(0);
+            let labels = error
+                .secondary
+                .iter()
+                .map(|label| (label.span, label.msg.clone()))
+                .collect::<Vec<_>>();
+            (error.msg, labels)
+        };
+
+        let (msg, labels) = check("var a: Bool = 1 + 2");
+        assert_eq!(
+            msg,
+            "Mismatched types in var declaration: expected Bool, found Int"
+        );
+        assert_eq!(
+            labels,
+            vec![
+                (Span::new(7, 11), String::from("Declared as Bool")),
+                (Span::new(14, 19), String::from("Found Int")),
+            ]
+        );
+
+        let (msg, labels) = check("var a = 1; a = true");
+        assert_eq!(
+            msg,
+            "Mismatched types in assignment: expected Int, found Bool"
+        );
+        assert_eq!(
+            labels,
+            vec![
+                (Span::new(11, 12), String::from("Variable has type Int")),
+                (Span::new(15, 19), String::from("Found Bool")),
+            ]
+        );
+
+        let (msg, labels) = check("if true then 1 else false");
+        assert_eq!(
+            msg,
+            "Mismatched types in if-then-else branches: expected Int, found Bool"
+        );
+        assert_eq!(
+            labels,
+            vec![
+                (
+                    Span::new(13, 14),
+                    String::from("Expected Int because of this branch")
+                ),
+                (Span::new(20, 25), String::from("Found Bool")),
+            ]
+        );
+
+        let (msg, labels) = check("true + 1");
+        assert_eq!(
+            msg,
+            "Mismatched argument types for operator +: expected (Int, Int), found (Bool, Int)"
+        );
+        assert_eq!(
+            labels,
+            vec![(Span::new(0, 4), String::from("Expected Int, found Bool"))]
+        );
+
+        let (msg, labels) = check("1 == true");
+        assert_eq!(
+            msg,
+            "No overload of operator == matches argument types (Int, Bool)"
+        );
+        assert_eq!(
+            labels,
+            vec![
+                (Span::new(0, 1), String::from("Int")),
+                (Span::new(5, 9), String::from("Bool")),
+            ]
+        );
+
+        let (msg, labels) = check("print_int(print_int)");
+        assert_eq!(msg, "print_int is a function and can only be called");
+        assert!(labels.is_empty());
+
+        // Unary operators are named as written, not by the symbols they are looked up by
+        let (msg, labels) = check("not 1");
+        assert_eq!(
+            msg,
+            "Mismatched argument types for operator not: expected (Bool), found (Int)"
+        );
+        assert_eq!(
+            labels,
+            vec![(Span::new(4, 5), String::from("Expected Bool, found Int"))]
+        );
+
+        let (msg, _) = check("-true");
+        assert_eq!(
+            msg,
+            "Mismatched argument types for operator -: expected (Int), found (Bool)"
+        );
+
+        let (msg, labels) = check("print_int(1, 2)");
+        assert_eq!(
+            msg,
+            "Wrong number of arguments for function print_int: expected 1, found 2"
+        );
+        assert!(labels.is_empty());
+    }
+
+    fn get_errors(code: &str) -> Vec<String> {
+        let tokens = tokenize(code).unwrap();
+        let mut ast = parse(&tokens).unwrap();
+        type_check(&mut ast, &mut SymTab::new_type_table())
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.msg)
+            .collect()
+    }
+
+    #[test]
+    fn test_all_errors_reported() {
+        let result = get_errors("var a: Int = true; if 1 then 2; while a do 3; b");
+        assert_eq!(
+            result,
+            vec![
+                "Mismatched types in var declaration: expected Int, found Bool",
+                "Non-bool as if-then-else condition: expected Bool, found Int",
+                "Non-bool as while-do condition: expected Bool, found Int",
+                "No symbol b found!",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors_not_cascaded() {
+        let result = get_errors("var a = b; var c: Bool = a; print(a * 2); a == true");
+        assert_eq!(result, vec!["No symbol b found!"]);
+
+        // The result type of an operator is known even if its operands had errors
+        let result = get_errors("var a = b + 1; a and true");
+        assert_eq!(
+            result,
+            vec![
+                "No symbol b found!",
+                "Mismatched argument types for operator and: expected (Bool, Bool), found (Int, Bool)",
+            ]
+        );
+
+        let result = get_errors("if (1 and true) then 2 else false");
+        assert_eq!(
+            result,
+            vec![
+                "Mismatched argument types for operator and: expected (Bool, Bool), found (Int, Bool)",
+                "Mismatched types in if-then-else branches: expected Int, found Bool",
+            ]
+        );
+
+        let result = get_errors("{ var x = foo(); x = 1; if x then x else not x }");
+        assert_eq!(result, vec!["No symbol foo found!"]);
+    }
+
+    #[test]
+    fn test_declared_type_after_error() {
+        let result = get_errors("var a: Int = true; a + 1; a and true");
+        assert_eq!(
+            result,
+            vec![
+                "Mismatched types in var declaration: expected Int, found Bool",
+                "Mismatched argument types for operator and: expected (Bool, Bool), found (Int, Bool)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_name_suggestions() {
+        let check = |code| {
+            let tokens = tokenize(code).unwrap();
+            let mut ast = parse(&tokens).unwrap();
+            type_check(&mut ast, &mut SymTab::new_type_table())
+                .unwrap_err()
+                .remove(0)
+        };
+
+        let error = check("var count = 1; { var cont = 2; cout + 1 }");
+        assert_eq!(error.msg, "No symbol cout found!");
+        assert_eq!(error.primary.loc, CodeLocation::new(1, 32));
+        assert_eq!(error.notes, vec!["Did you mean cont or count?"]);
+
+        let error = check("pritn(1)");
+        assert_eq!(error.notes, vec!["Did you mean print?"]);
+
+        let error = check("xyz");
+        assert!(error.notes.is_empty());
+    }
+
     #[test]
     fn test_node_type() {
         let tokens = tokenize("1").unwrap();
@@ -1,21 +1,84 @@
-use std::fmt;
+use std::{cell::RefCell, fmt};
+
+thread_local! {
+    // Command-line arguments given to the interpreted program
+    static PROGRAM_ARGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn set_program_args(args: Vec<String>) {
+    PROGRAM_ARGS.with_borrow_mut(|program_args| *program_args = args);
+}
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Type {
     Int,
     Bool,
     Func(Vec<Type>, Box<Type>),
+    // Implementing symbol and its function type for each overload
+    Overloaded(Vec<(&'static str, Type)>),
     Unit,
+    // Given to expressions that failed to type check. It is accepted wherever any type is
+    // expected, so a single mistake isn't reported again by every expression using it.
+    Error,
+}
+
+impl Type {
+    pub fn accepts(&self, other: &Type) -> bool {
+        self == other || *self == Type::Error || *other == Type::Error
+    }
+
+    // Argument types of a call or a signature, like (Int, Bool)
+    pub fn list(types: &[Type]) -> String {
+        let types = types.iter().map(Type::to_string).collect::<Vec<_>>();
+        format!("({})", types.join(", "))
+    }
+}
+
+// Written the way types are in the language, e.g. (Int, Int) => Bool for a function
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Func(arg_types, ret_type) => {
+                write!(f, "{} => {}", Type::list(arg_types), ret_type)
+            }
+            Type::Overloaded(overloads) => {
+                let signatures = overloads
+                    .iter()
+                    .map(|(_, signature)| signature.to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "{}", signatures.join(" | "))
+            }
+            Type::Unit => write!(f, "Unit"),
+            Type::Error => write!(f, "<error>"),
+        }
+    }
 }
 
+#[allow(unpredictable_function_pointer_comparisons)]
 #[derive(PartialEq, PartialOrd, Debug, Copy, Clone)]
 pub enum Value {
     Int(i128),
     Bool(bool),
-    Func(fn(&[Value]) -> Value),
+    Func(fn(&[Value]) -> Result<Value, Stop>),
     None(),
 }
 
+// Why a builtin returned without a value
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    Error(String),
+    // Ending the program is left to whatever is running it
+    Exit(i32),
+}
+
+impl From<String> for Stop {
+    fn from(msg: String) -> Self {
+        Stop::Error(msg)
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -27,119 +90,167 @@ impl fmt::Display for Value {
     }
 }
 
+// The interpreter runs code that hasn't been type checked, so the builtins check their arguments
 impl Value {
-    pub fn add(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-
-        let Value::Int(lhs) = args[0] else {
-            panic!("Can't apply + to non-ints!")
-        };
-        let Value::Int(rhs) = args[1] else {
-            panic!("Can't apply + to non-ints!")
-        };
-
-        Value::Int(lhs + rhs)
+    fn args<const N: usize>(args: &[Self], name: &str) -> Result<[Self; N], String> {
+        args.try_into().map_err(|_| {
+            format!(
+                "{name} takes {N} argument(s) but {} were given!",
+                args.len()
+            )
+        })
     }
 
-    pub fn mul(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-
-        let Value::Int(lhs) = args[0] else {
-            panic!("Can't apply * to non-ints!")
-        };
-        let Value::Int(rhs) = args[1] else {
-            panic!("Can't apply * to non-ints!")
-        };
-
-        Value::Int(lhs * rhs)
+    fn int_args(args: &[Self], op: &str) -> Result<(i128, i128), String> {
+        match Self::args(args, op)? {
+            [Value::Int(lhs), Value::Int(rhs)] => Ok((lhs, rhs)),
+            _ => Err(format!("Can't apply {op} to non-ints!")),
+        }
     }
 
-    pub fn sub(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-
-        let Value::Int(lhs) = args[0] else {
-            panic!("Can't apply - to non-ints!")
-        };
-        let Value::Int(rhs) = args[1] else {
-            panic!("Can't apply - to non-ints!")
-        };
+    fn overflow() -> Stop {
+        String::from("Integer overflow!").into()
+    }
 
-        Value::Int(lhs - rhs)
+    pub fn add(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, "+")?;
+        lhs.checked_add(rhs)
+            .map(Value::Int)
+            .ok_or_else(Self::overflow)
     }
 
-    pub fn div(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
+    pub fn mul(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, "*")?;
+        lhs.checked_mul(rhs)
+            .map(Value::Int)
+            .ok_or_else(Self::overflow)
+    }
 
-        let Value::Int(lhs) = args[0] else {
-            panic!("Can't apply / to non-ints!")
-        };
-        let Value::Int(rhs) = args[1] else {
-            panic!("Can't apply / to non-ints!")
-        };
+    pub fn sub(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, "-")?;
+        lhs.checked_sub(rhs)
+            .map(Value::Int)
+            .ok_or_else(Self::overflow)
+    }
 
-        Value::Int(lhs / rhs)
+    pub fn div(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, "/")?;
+        if rhs == 0 {
+            return Err(String::from("Division by zero!").into());
+        }
+        lhs.checked_div(rhs)
+            .map(Value::Int)
+            .ok_or_else(Self::overflow)
     }
 
-    pub fn rem(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
+    pub fn rem(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, "%")?;
+        if rhs == 0 {
+            return Err(String::from("Division by zero!").into());
+        }
+        lhs.checked_rem(rhs)
+            .map(Value::Int)
+            .ok_or_else(Self::overflow)
+    }
 
-        let Value::Int(lhs) = args[0] else {
-            panic!("Can't apply % to non-ints!")
-        };
-        let Value::Int(rhs) = args[1] else {
-            panic!("Can't apply % to non-ints!")
-        };
+    pub fn eq(args: &[Self]) -> Result<Self, Stop> {
+        let [lhs, rhs] = Self::args(args, "==")?;
+        Ok(Value::Bool(lhs == rhs))
+    }
 
-        Value::Int(lhs / rhs)
+    pub fn neq(args: &[Self]) -> Result<Self, Stop> {
+        let [lhs, rhs] = Self::args(args, "!=")?;
+        Ok(Value::Bool(lhs != rhs))
     }
 
-    pub fn eq(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-        Value::Bool(args[0] == args[1])
+    pub fn lt(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, "<")?;
+        Ok(Value::Bool(lhs < rhs))
     }
 
-    pub fn neq(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-        Value::Bool(args[0] != args[1])
+    pub fn le(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, "<=")?;
+        Ok(Value::Bool(lhs <= rhs))
     }
 
-    pub fn lt(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-        Value::Bool(args[0] < args[1])
+    pub fn gt(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, ">")?;
+        Ok(Value::Bool(lhs > rhs))
     }
 
-    pub fn le(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-        Value::Bool(args[0] <= args[1])
+    pub fn ge(args: &[Self]) -> Result<Self, Stop> {
+        let (lhs, rhs) = Self::int_args(args, ">=")?;
+        Ok(Value::Bool(lhs >= rhs))
     }
 
-    pub fn gt(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-        Value::Bool(args[0] > args[1])
+    pub fn not(args: &[Self]) -> Result<Self, Stop> {
+        let [Value::Bool(val)] = Self::args(args, "not")? else {
+            return Err(String::from("Can't apply 'not' to non-bools!").into());
+        };
+
+        Ok(Value::Bool(!val))
     }
 
-    pub fn ge(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 2);
-        Value::Bool(args[0] >= args[1])
+    pub fn neg(args: &[Self]) -> Result<Self, Stop> {
+        let [Value::Int(val)] = Self::args(args, "-")? else {
+            return Err(String::from("Can't apply negation to non-ints!").into());
+        };
+
+        val.checked_neg().map(Value::Int).ok_or_else(Self::overflow)
     }
 
-    pub fn not(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 1);
+    pub fn assert(args: &[Self]) -> Result<Self, Stop> {
+        let [Value::Bool(val)] = Self::args(args, "assert")? else {
+            return Err(String::from("Can't assert non-bools!").into());
+        };
 
-        let Value::Bool(val) = args[0] else {
-            panic!("Can't apply 'not' to non-bools!")
+        if val {
+            Ok(Value::None())
+        } else {
+            Err(String::from("assertion failed").into())
+        }
+    }
+
+    pub fn exit(args: &[Self]) -> Result<Self, Stop> {
+        let [Value::Int(code)] = Self::args(args, "exit")? else {
+            return Err(String::from("Can't exit with a non-int!").into());
         };
 
-        Value::Bool(!val)
+        // Only the low 8 bits of the code reach the parent process, as in compiled programs
+        Err(Stop::Exit((code & 0xff) as i32))
     }
 
-    pub fn neg(args: &[Self]) -> Self {
-        assert_eq!(args.len(), 1);
+    pub fn arg_count(args: &[Self]) -> Result<Self, Stop> {
+        let [] = Self::args(args, "arg_count")?;
+        Ok(Value::Int(
+            PROGRAM_ARGS.with_borrow(|program_args| program_args.len() as i128),
+        ))
+    }
 
-        let Value::Int(val) = args[0] else {
-            panic!("Can't apply negation to non-ints!")
+    pub fn arg_int(args: &[Self]) -> Result<Self, Stop> {
+        let [Value::Int(index)] = Self::args(args, "arg_int")? else {
+            return Err(String::from("Can't index arguments with a non-int!").into());
         };
 
-        Value::Int(-val)
+        PROGRAM_ARGS.with_borrow(|program_args| {
+            let Some(arg) = usize::try_from(index)
+                .ok()
+                .and_then(|index| program_args.get(index))
+            else {
+                return Err(format!("No command-line argument with index {index}").into());
+            };
+
+            // Same format as accepted by the compiled program, so no leading plus sign
+            match arg.parse::<i64>() {
+                Ok(val) if !arg.starts_with('+') => Ok(Value::Int(val as i128)),
+                _ => Err(format!("Command-line argument '{arg}' is not an integer").into()),
+            }
+        })
+    }
+
+    pub fn print(args: &[Self]) -> Result<Self, Stop> {
+        let [val] = Self::args(args, "print")?;
+        println!("{}", val);
+        Ok(Value::None())
     }
 }
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+
+use crate::compiler::{
+    ast::{AstNode, Expression::*},
+    diagnostic::Diagnostic,
+    token::{CodeLocation, Span},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    UnusedVariable,
+    UnreachableCode,
+    Shadowing,
+    UselessExpression,
+}
+
+impl Warning {
+    pub const ALL: [Warning; 4] = [
+        Warning::UnusedVariable,
+        Warning::UnreachableCode,
+        Warning::Shadowing,
+        Warning::UselessExpression,
+    ];
+
+    // Used in the -W flags that enable and disable the warning
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::UnusedVariable => "unused-variable",
+            Warning::UnreachableCode => "unreachable-code",
+            Warning::Shadowing => "shadowing",
+            Warning::UselessExpression => "useless-expression",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WarningOptions {
+    enabled: [bool; Warning::ALL.len()],
+    // Fail the compilation if there are any warnings
+    pub as_errors: bool,
+}
+
+impl Default for WarningOptions {
+    fn default() -> Self {
+        Self {
+            enabled: [true; Warning::ALL.len()],
+            as_errors: false,
+        }
+    }
+}
+
+impl WarningOptions {
+    pub fn enabled(&self, warning: Warning) -> bool {
+        self.enabled[warning as usize]
+    }
+
+    // Accepts -Werror, -W<name> to enable a warning and -Wno-<name> to disable it
+    pub fn apply_flag(&mut self, flag: &str) -> Result<(), String> {
+        let Some(setting) = flag.strip_prefix("-W") else {
+            return Err(format!("Invalid warning flag {}", flag));
+        };
+
+        if setting == "error" {
+            self.as_errors = true;
+            return Ok(());
+        }
+
+        let (name, enabled) = match setting.strip_prefix("no-") {
+            Some(name) => (name, false),
+            None => (setting, true),
+        };
+        let Some(warning) = Warning::ALL.iter().find(|warning| warning.name() == name) else {
+            let names = Warning::ALL.map(|warning| warning.name());
+            return Err(format!(
+                "Unknown warning {} in flag {}, expected one of {}",
+                name,
+                flag,
+                names.join(", ")
+            ));
+        };
+
+        self.enabled[*warning as usize] = enabled;
+        Ok(())
+    }
+}
+
+struct Variable {
+    loc: CodeLocation,
+    span: Span,
+    used: bool,
+}
+
+struct WarningState<'source> {
+    options: WarningOptions,
+    scopes: Vec<HashMap<&'source str, Variable>>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl WarningState<'_> {
+    fn warn(&mut self, warning: Warning, diagnostic: Diagnostic) {
+        if self.options.enabled(warning) {
+            self.warnings.push(diagnostic);
+        }
+    }
+}
+
+// Looks for code that is valid but likely a mistake. Runs on a type checked AST, after macro
+// expansion, and returns the warnings in source order.
+pub fn check_warnings(ast: &AstNode, options: WarningOptions) -> Vec<Diagnostic> {
+    let mut state = WarningState {
+        options,
+        scopes: vec![HashMap::new()],
+        warnings: Vec::new(),
+    };
+
+    visit(ast, &mut state);
+    end_scope(&mut state);
+
+    state
+        .warnings
+        .sort_by_key(|warning| warning.primary.span.start);
+    state.warnings
+}
+
+fn visit<'source>(ast: &AstNode<'source>, state: &mut WarningState<'source>) {
+    match &ast.expr {
+        Identifier(name) => {
+            let scope = state
+                .scopes
+                .iter_mut()
+                .rev()
+                .find(|scope| scope.contains_key(name));
+            if let Some(variable) = scope.and_then(|scope| scope.get_mut(name)) {
+                variable.used = true;
+            }
+        }
+        // Assigning to a variable doesn't count as using it
+        BinaryOp(left, "=", right) if matches!(left.expr, Identifier(_)) => visit(right, state),
+        VarDeclaration(name, expr, _) => {
+            visit(expr, state);
+
+            let outer = state.scopes[..state.scopes.len() - 1]
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(name));
+            if let Some(outer) = outer {
+                let diagnostic = Diagnostic::warning(
+                    format!("Variable {} shadows a variable in an outer scope", name),
+                    ast.loc,
+                    ast.span,
+                )
+                .with_label(
+                    outer.loc,
+                    outer.span,
+                    String::from("Shadowed variable"),
+                );
+                state.warn(Warning::Shadowing, diagnostic);
+            }
+
+            let variable = Variable {
+                loc: ast.loc,
+                span: ast.span,
+                used: false,
+            };
+            state
+                .scopes
+                .last_mut()
+                .expect("Scopes should never be empty!")
+                .insert(name, variable);
+        }
+        Block(expressions) => {
+            state.scopes.push(HashMap::new());
+
+            for (i, expression) in expressions.iter().enumerate() {
+                visit(expression, state);
+
+                // The last expression is the value of the block
+                if i + 1 < expressions.len() {
+                    check_useless(expression, state);
+                }
+            }
+            check_unreachable(expressions, state);
+
+            end_scope(state);
+        }
+        Defer(expr) => {
+            visit(expr, state);
+            check_useless(expr, state);
+        }
+        _ => {
+            for child in ast.children() {
+                visit(child, state);
+            }
+        }
+    }
+}
+
+// Variables generated by macro expansion contain a '$', and names starting with an underscore
+// are unused on purpose
+fn end_scope(state: &mut WarningState) {
+    let scope = state.scopes.pop().expect("Scopes should never be empty!");
+
+    let mut unused = scope
+        .into_iter()
+        .filter(|(name, variable)| !variable.used && !name.starts_with('_') && !name.contains('$'))
+        .collect::<Vec<_>>();
+    unused.sort_by_key(|(_, variable)| variable.span.start);
+
+    for (name, variable) in unused {
+        let diagnostic = Diagnostic::warning(
+            format!("Unused variable {}", name),
+            variable.loc,
+            variable.span,
+        )
+        .with_note(format!(
+            "If this is intentional, prefix it with an underscore: _{}",
+            name
+        ));
+        state.warn(Warning::UnusedVariable, diagnostic);
+    }
+}
+
+// Expressions without side effects, whose value is discarded
+fn check_useless(ast: &AstNode, state: &mut WarningState) {
+    if is_pure(ast) {
+        let diagnostic = Diagnostic::warning(
+            String::from("Value of expression is never used"),
+            ast.loc,
+            ast.span,
+        );
+        state.warn(Warning::UselessExpression, diagnostic);
+    }
+}
+
+fn is_pure(ast: &AstNode) -> bool {
+    match &ast.expr {
+        IntLiteral(_) | BoolLiteral(_) | Identifier(_) => true,
+        UnaryOp(_, expr) => is_pure(expr),
+        BinaryOp(left, op, right) => *op != "=" && is_pure(left) && is_pure(right),
+        _ => false,
+    }
+}
+
+fn check_unreachable(expressions: &[AstNode], state: &mut WarningState) {
+    let Some(diverging) = expressions.iter().position(diverges) else {
+        return;
+    };
+
+    // A semicolon after the last expression leaves an empty expression that isn't in the source
+    let unreachable = expressions[diverging + 1..]
+        .iter()
+        .filter(|expression| expression.span.start != expression.span.end)
+        .collect::<Vec<_>>();
+    let (Some(first), Some(last)) = (unreachable.first(), unreachable.last()) else {
+        return;
+    };
+
+    let cause = &expressions[diverging];
+    let diagnostic = Diagnostic::warning(
+        String::from("Unreachable code"),
+        first.loc,
+        first.span.to(last.span),
+    )
+    .with_label(
+        cause.loc,
+        cause.span,
+        String::from("Any code following this expression is unreachable"),
+    );
+    state.warn(Warning::UnreachableCode, diagnostic);
+}
+
+// Whether evaluating the expression never finishes, because of an infinite loop or exiting the
+// program. There is no way to break out of a loop, so any loop on a literal true never ends.
+fn diverges(ast: &AstNode) -> bool {
+    match &ast.expr {
+        While(condition_expr, _) => {
+            matches!(condition_expr.expr, BoolLiteral(true)) || diverges(condition_expr)
+        }
+        FunCall("exit", _) => true,
+        Conditional(condition_expr, then_expr, else_expr) => {
+            diverges(condition_expr)
+                || else_expr
+                    .as_ref()
+                    .is_some_and(|else_expr| diverges(then_expr) && diverges(else_expr))
+        }
+        // Deferred expressions only run when the block ends
+        Defer(_) => false,
+        // Only the left side of a short-circuiting operator always runs
+        BinaryOp(left, "and" | "or", _) => diverges(left),
+        _ => ast.children().into_iter().any(diverges),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{
+        diagnostic::Severity,
+        macro_expander::{expand_macros, FreshNames},
+        parser::parse,
+        symtab::SymTab,
+        tokenizer::tokenize,
+        type_checker::type_check,
+    };
+
+    fn warnings_with(code: &str, flags: &[&str]) -> Vec<String> {
+        let names = FreshNames::default();
+        let tokens = tokenize(code).unwrap();
+        let mut ast = parse(&tokens).unwrap();
+        expand_macros(&mut ast, &names).unwrap();
+        type_check(&mut ast, &mut SymTab::new_type_table()).unwrap();
+
+        let mut options = WarningOptions::default();
+        for flag in flags {
+            options.apply_flag(flag).unwrap();
+        }
+        check_warnings(&ast, options)
+            .into_iter()
+            .map(|warning| warning.msg)
+            .collect()
+    }
+
+    fn warnings(code: &str) -> Vec<String> {
+        warnings_with(code, &[])
+    }
+
+    #[test]
+    fn test_no_warnings() {
+        let result = warnings("var a = 1; { var b = a; print(b); }; while a > 0 do a = a - 1; a");
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_unused_variable() {
+        let result = warnings("var a = 1; var b = 2; { var c = b; }; var _d = 3;");
+        assert_eq!(result, vec!["Unused variable a", "Unused variable c"]);
+
+        let result = warnings("var a = 1; a = 2;");
+        assert_eq!(result, vec!["Unused variable a"]);
+
+        let result = warnings("{ var a = 1; defer print(a); }");
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_unused_variable_location() {
+        let tokens = tokenize("print(1);\nvar a = 1;").unwrap();
+        let ast = parse(&tokens).unwrap();
+        let result = check_warnings(&ast, WarningOptions::default());
+
+        assert_eq!(result[0].severity, Severity::Warning);
+        assert_eq!(result[0].primary.loc, CodeLocation::new(2, 1));
+        assert_eq!(result[0].primary.span, Span::new(10, 19));
+    }
+
+    #[test]
+    fn test_unused_variable_in_macro() {
+        let result = warnings("macro twice(x) { var y = x; x }; twice(1)");
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let result = warnings("var a = 1; { var a = 2; a }; a");
+        assert_eq!(
+            result,
+            vec!["Variable a shadows a variable in an outer scope"]
+        );
+
+        let result = warnings("{ var a = 1; a }; { var a = 2; a }");
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let result = warnings("var a = 1; while true do a = 2; print(a); a");
+        assert_eq!(result, vec!["Unreachable code"]);
+
+        let result = warnings("{ exit(1); 2 }");
+        assert_eq!(result, vec!["Unreachable code"]);
+
+        let result = warnings("if true then exit(1) else while true do 1; 2");
+        assert_eq!(result, vec!["Unreachable code"]);
+
+        let result = warnings("while true do 1;");
+        assert!(result.is_empty());
+
+        let result = warnings("if read_int() > 0 then exit(1); 2");
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_useless_expression() {
+        let result = warnings("var a = 1; 1 + 2; a; not true; a = 2; print(a); 3");
+        assert_eq!(
+            result,
+            vec![
+                "Value of expression is never used",
+                "Value of expression is never used",
+                "Value of expression is never used",
+            ]
+        );
+
+        let result = warnings("{ defer 1; }");
+        assert_eq!(result, vec!["Value of expression is never used"]);
+    }
+
+    #[test]
+    fn test_flags() {
+        let code = "var a = 1; { var a = 2; 3 }";
+        assert_eq!(warnings(code).len(), 3);
+        assert_eq!(warnings_with(code, &["-Wno-shadowing"]).len(), 2);
+        assert_eq!(
+            warnings_with(code, &["-Wno-unused-variable", "-Wno-shadowing"]).len(),
+            0
+        );
+        assert_eq!(
+            warnings_with(code, &["-Wno-shadowing", "-Wshadowing"]).len(),
+            3
+        );
+
+        let mut options = WarningOptions::default();
+        assert!(options.apply_flag("-Wno-everything").is_err());
+        assert!(options.apply_flag("shadowing").is_err());
+    }
+}
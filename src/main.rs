@@ -1,16 +1,67 @@
-use std::env;
+use std::{env, fs, thread};
 
 mod compiler;
 mod server;
 
 fn main() {
+    thread::Builder::new()
+        .stack_size(compiler::STACK_SIZE)
+        .spawn(run)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn run() {
     let args: Vec<String> = env::args().collect();
 
     if let Some(flag) = args.get(1) {
         if flag == "-i" {
-            compiler::start_interpreter();
+            // Remaining arguments are passed to the interpreted program
+            compiler::start_interpreter(args[2..].to_vec());
         } else if flag == "-c" {
-            compiler::start_compiler();
+            let mut options = compiler::CompilerOptions {
+                exit_with_result: args[2..].iter().any(|arg| arg == "--exit-with-result"),
+                ..Default::default()
+            };
+            for arg in args[2..].iter().filter(|arg| arg.starts_with("-W")) {
+                if let Err(e) = options.warnings.apply_flag(arg) {
+                    eprintln!("{e}");
+                    return;
+                }
+            }
+            if let Some(path) = args[2..]
+                .iter()
+                .find_map(|arg| arg.strip_prefix("--lint-config="))
+            {
+                let config = fs::read_to_string(path)
+                    .map_err(|e| format!("Unable to read lint configuration {path}: {e}"))
+                    .and_then(|config| compiler::LintConfig::parse(&config));
+                match config {
+                    Ok(lints) => options.lints = lints,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        return;
+                    }
+                }
+            }
+            let format = match args[2..]
+                .iter()
+                .find_map(|arg| arg.strip_prefix("--message-format="))
+                .map(compiler::MessageFormat::from_name)
+                .transpose()
+            {
+                Ok(format) => format.unwrap_or_default(),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return;
+                }
+            };
+            compiler::start_compiler(options, format);
+        } else if flag == "-f" {
+            compiler::start_formatter(args[2..].iter().any(|arg| arg == "--outline"));
+        } else if flag == "--explain" {
+            compiler::start_explain(args.get(2).map(String::as_str));
         }
     } else {
         server::start("::".parse().unwrap(), 3000);
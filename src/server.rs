@@ -17,9 +17,12 @@ pub fn start(address: IpAddr, port: u16) {
     for stream in listener.incoming() {
         let stream = stream.unwrap();
 
-        thread::spawn(|| {
-            handle_connection(stream);
-        });
+        thread::Builder::new()
+            .stack_size(compiler::STACK_SIZE)
+            .spawn(|| {
+                handle_connection(stream);
+            })
+            .unwrap();
     }
 }
 
@@ -35,11 +38,11 @@ fn handle_connection(mut stream: TcpStream) {
         "ping" => println!("ping"),
         "compile" => {
             let program = json_request["code"].as_str().unwrap();
-            let output = compiler::compile(program);
+            let file = json_request["file"].as_str().unwrap_or("<input>");
 
-            let response = match output {
-                Ok(output) => format!("{{\"program\": \"{output}\"}}"),
-                Err(e) => format!("{{\"error\": {}}}", json::stringify(format!("{e}"))),
+            let response = match request_options(&json_request) {
+                Ok((options, format)) => compile_response(program, file, &options, format),
+                Err(e) => format!("{{\"error\": {}}}", json::stringify(e)),
             };
 
             stream.write_all(response.as_bytes()).unwrap();
@@ -47,3 +50,73 @@ fn handle_connection(mut stream: TcpStream) {
         _ => panic!("Unexpected command!"),
     }
 }
+
+// The compiler options and message format of a compile request, or a message for the first one
+// that isn't valid
+fn request_options(
+    json_request: &json::JsonValue,
+) -> Result<(compiler::CompilerOptions, compiler::MessageFormat), String> {
+    let defaults = compiler::CompilerOptions::default();
+    let mut options = compiler::CompilerOptions {
+        exit_with_result: json_request["exit_with_result"].as_bool().unwrap_or(false),
+        // Clients can lower the limit, but not raise it past what the stack can take
+        max_nesting_depth: json_request["max_nesting_depth"]
+            .as_usize()
+            .map_or(defaults.max_nesting_depth, |depth| {
+                depth.min(defaults.max_nesting_depth)
+            }),
+        warnings: defaults.warnings,
+        lints: defaults.lints,
+    };
+
+    // Diagnostics are rendered as text by default, or given as JSON objects with
+    // "message_format": "json"
+    let format = json_request["message_format"]
+        .as_str()
+        .map(compiler::MessageFormat::from_name)
+        .transpose()?
+        .unwrap_or_default();
+
+    // Same flags as on the command line, e.g. ["-Wno-shadowing", "-Werror"]
+    for flag in json_request["warnings"].members() {
+        flag.as_str()
+            .ok_or_else(|| format!("Invalid warning flag {}", flag))
+            .and_then(|flag| options.warnings.apply_flag(flag))?;
+    }
+
+    // Contents of a lint configuration file, in the same format as on the command line
+    if let Some(config) = json_request["lint_config"].as_str() {
+        options.lints = compiler::LintConfig::parse(config)?;
+    }
+
+    Ok((options, format))
+}
+
+fn compile_response(
+    program: &str,
+    file: &str,
+    options: &compiler::CompilerOptions,
+    format: compiler::MessageFormat,
+) -> String {
+    match (compiler::compile(program, options), format) {
+        (Ok((output, warnings)), compiler::MessageFormat::Json) => format!(
+            "{{\"program\": \"{output}\", \"warnings\": {}}}",
+            compiler::diagnostics_json(&warnings, program, file).dump()
+        ),
+        (Err(diagnostics), compiler::MessageFormat::Json) => format!(
+            "{{\"errors\": {}}}",
+            compiler::diagnostics_json(&diagnostics, program, file).dump()
+        ),
+        (Ok((output, warnings)), _) if warnings.is_empty() => {
+            format!("{{\"program\": \"{output}\"}}")
+        }
+        (Ok((output, warnings)), _) => format!(
+            "{{\"program\": \"{output}\", \"warnings\": {}}}",
+            json::stringify(compiler::render_diagnostics(&warnings, program))
+        ),
+        (Err(diagnostics), _) => format!(
+            "{{\"error\": {}}}",
+            json::stringify(compiler::render_diagnostics(&diagnostics, program))
+        ),
+    }
+}